@@ -0,0 +1,323 @@
+/// This module restricts which clients may call the REST API and the gRPC health service. Clients can be limited by source
+/// address, separately for reading and changing the status, and each client address gets a
+/// token bucket that limits its request rate. Behind a load balancer or reverse proxy the client
+/// address is taken from the `X-Forwarded-For` header, but only when the request comes from a
+/// trusted proxy.
+use crate::auth::{required_role, Role};
+use crate::config::AccessConfig;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use log::debug;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The maximum number of clients to track. When it is reached, the client that made its last
+/// request the longest time ago is forgotten.
+const MAX_CLIENTS: usize = 10_000;
+
+/// How often the buckets of idle clients are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Parses a list of networks in CIDR notation. Single addresses are accepted too.
+fn parse_networks(networks: &[String]) -> Result<Vec<IpNet>, String> {
+    networks
+        .iter()
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("Invalid network: {}", network))
+        })
+        .collect()
+}
+
+/// Whether the address is in one of the networks. An empty list allows every address.
+fn is_allowed(networks: &[IpNet], addr: &IpAddr) -> bool {
+    networks.is_empty() || networks.iter().any(|network| network.contains(addr))
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket per client address. Every request takes a token, and the tokens are refilled at
+/// a steady rate up to the burst size.
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of the client. Returns the number of seconds until a token
+    /// is available if the bucket is empty.
+    fn take(&self, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_CLIENTS && !buckets.contains_key(&client) {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated)
+                .map(|(addr, _)| *addr);
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil() as u64)
+        }
+    }
+
+    /// Forgets the buckets that have been refilled completely, they are the same as new ones.
+    fn prune(&self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+        });
+    }
+}
+
+/// The address of the client that made a request, which is added to the request extensions.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub IpAddr);
+
+/// The networks that may call the API and the rate limit per client.
+#[derive(Default)]
+pub struct AccessPolicy {
+    read: Vec<IpNet>,
+    write: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+    limiter: Option<RateLimiter>,
+}
+
+impl AccessPolicy {
+    pub fn new(config: &AccessConfig) -> Result<Self, String> {
+        let limiter = config
+            .rate_limit
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| RateLimiter {
+                rate,
+                burst: config.rate_limit_burst.max(1) as f64,
+                buckets: Mutex::new(HashMap::new()),
+            });
+        Ok(AccessPolicy {
+            read: parse_networks(&config.allow_read)?,
+            write: parse_networks(&config.allow_write)?,
+            trusted_proxies: parse_networks(&config.trusted_proxies)?,
+            limiter,
+        })
+    }
+
+    /// Returns the address of the client. When the request comes through trusted proxies, this is
+    /// the last address in `X-Forwarded-For` that was not added by a trusted proxy.
+    ///
+    /// IPv4 addresses that are mapped to IPv6, like the peers of a server that listens on `::`,
+    /// are converted back to IPv4, so they match the IPv4 networks of the policy.
+    fn client_addr(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for hop in forwarded.iter().rev() {
+            if !is_trusted_proxy(&self.trusted_proxies, &client) {
+                break;
+            }
+            match hop.trim().parse::<IpAddr>() {
+                Ok(addr) => client = addr.to_canonical(),
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+/// Whether the address is one of the trusted proxies. Unlike the allowlists, an empty list trusts
+/// nobody.
+fn is_trusted_proxy(proxies: &[IpNet], addr: &IpAddr) -> bool {
+    proxies.iter().any(|network| network.contains(addr))
+}
+
+/// Periodically forgets the clients that have been idle long enough to have a full bucket again.
+pub async fn prune(policy: Arc<AccessPolicy>) {
+    let Some(limiter) = &policy.limiter else {
+        return;
+    };
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        limiter.prune(Instant::now());
+    }
+}
+
+/// Whether the request is a gRPC call.
+fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+/// Middleware that applies the access policy. Requests over the Unix socket have no client
+/// address, they are protected by the permissions of the socket.
+pub async fn restrict(
+    State(policy): State<Arc<AccessPolicy>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>() else {
+        return next.run(request).await;
+    };
+    let client = policy.client_addr(peer.ip(), request.headers());
+
+    // The gRPC health service only reads the status, although its calls are POST requests.
+    let role = if is_grpc(request.headers()) {
+        Role::Read
+    } else {
+        required_role(request.method())
+    };
+    let allowed = match role {
+        Role::Read => &policy.read,
+        Role::Write => &policy.write,
+    };
+    if !is_allowed(allowed, &client) {
+        debug!(
+            "{} is not allowed to {} {}",
+            client,
+            request.method(),
+            request.uri()
+        );
+        return (StatusCode::FORBIDDEN, "Access denied.").into_response();
+    }
+
+    if let Some(limiter) = &policy.limiter {
+        if let Err(retry_after) = limiter.take(client, Instant::now()) {
+            debug!("Rate limit exceeded for {}", client);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                "Too many requests.",
+            )
+                .into_response();
+        }
+    }
+
+    request.extensions_mut().insert(ClientAddr(client));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AccessConfig {
+        AccessConfig {
+            allow_read: vec![],
+            allow_write: vec!["127.0.0.1".to_string(), "10.0.1.0/24".to_string()],
+            trusted_proxies: vec!["10.0.0.2".to_string()],
+            rate_limit: Some(1.0),
+            rate_limit_burst: 2,
+        }
+    }
+
+    #[test]
+    fn test_networks() {
+        let policy = AccessPolicy::new(&config()).unwrap();
+        assert!(is_allowed(&policy.read, &"192.168.1.1".parse().unwrap()));
+        assert!(is_allowed(&policy.write, &"127.0.0.1".parse().unwrap()));
+        assert!(is_allowed(&policy.write, &"10.0.1.20".parse().unwrap()));
+        assert!(!is_allowed(&policy.write, &"10.0.2.20".parse().unwrap()));
+
+        let mut invalid = config();
+        invalid.allow_read = vec!["10.0.0.0/33".to_string()];
+        assert!(AccessPolicy::new(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let policy = AccessPolicy::new(&config()).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4, 10.0.1.20".parse().unwrap());
+
+        // Only trusted proxies can tell who the client is.
+        let proxy = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "10.0.1.20".parse().unwrap();
+        assert_eq!(policy.client_addr(proxy, &headers), client);
+        let untrusted = "10.0.0.3".parse().unwrap();
+        assert_eq!(policy.client_addr(untrusted, &headers), untrusted);
+        assert_eq!(policy.client_addr(proxy, &HeaderMap::new()), proxy);
+
+        // Dual-stack listeners report IPv4 clients as IPv4-mapped IPv6 addresses.
+        let mapped = "::ffff:10.0.0.2".parse().unwrap();
+        assert_eq!(policy.client_addr(mapped, &headers), client);
+        let mapped = "::ffff:10.0.1.30".parse().unwrap();
+        let canonical: IpAddr = "10.0.1.30".parse().unwrap();
+        assert_eq!(policy.client_addr(mapped, &HeaderMap::new()), canonical);
+        assert!(is_allowed(&policy.write, &canonical));
+        headers.insert("x-forwarded-for", "::ffff:10.0.1.20".parse().unwrap());
+        assert_eq!(policy.client_addr(proxy, &headers), client);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let policy = AccessPolicy::new(&config()).unwrap();
+        let limiter = policy.limiter.unwrap();
+        let client = "10.0.1.20".parse().unwrap();
+        let other = "10.0.1.21".parse().unwrap();
+        let now = Instant::now();
+
+        // The burst is available immediately, then the tokens are refilled at the rate.
+        assert_eq!(limiter.take(client, now), Ok(()));
+        assert_eq!(limiter.take(client, now), Ok(()));
+        assert_eq!(limiter.take(client, now), Err(1));
+        assert_eq!(limiter.take(other, now), Ok(()));
+        assert_eq!(limiter.take(client, now + Duration::from_secs(1)), Ok(()));
+        assert_eq!(limiter.take(client, now + Duration::from_secs(1)), Err(1));
+
+        // Clients with a full bucket are forgotten.
+        limiter.prune(now + Duration::from_secs(1));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+        limiter.prune(now + Duration::from_secs(3));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let policy = AccessPolicy::new(&config()).unwrap();
+        let limiter = policy.limiter.unwrap();
+        let now = Instant::now();
+        let client = |i: usize| IpAddr::from([10, 1, (i / 256) as u8, (i % 256) as u8]);
+        for i in 0..MAX_CLIENTS {
+            assert_eq!(
+                limiter.take(client(i), now + Duration::from_millis(i as u64)),
+                Ok(())
+            );
+        }
+
+        // The client that was seen the longest time ago makes room for a new one.
+        let later = now + Duration::from_secs(60);
+        assert_eq!(limiter.take(client(MAX_CLIENTS), later), Ok(()));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_CLIENTS);
+        assert!(!buckets.contains_key(&client(0)));
+        assert!(buckets.contains_key(&client(1)));
+    }
+}
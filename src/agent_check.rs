@@ -0,0 +1,107 @@
+/// This module implements the HAProxy `agent-check` text protocol. HAProxy connects to the agent
+/// port, reads a single line describing the desired server state and closes the connection.
+/// See https://docs.haproxy.org/2.8/configuration.html#5.2-agent-check
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// How long to wait before accepting connections again after a failure, e.g. when we ran out of
+/// file descriptors, so the loop doesn't spin.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Accepts agent-check connections and replies with the current status.
+pub async fn serve(listener: TcpListener, status: Arc<Mutex<Status>>) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept agent-check connection: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let reply = response(&*status.lock().await);
+        debug!("Agent-check from {}: {}", peer, reply);
+        tokio::spawn(async move {
+            if let Err(e) = stream.write_all(format!("{}\n", reply).as_bytes()).await {
+                warn!("Failed to reply to agent-check from {}: {}", peer, e);
+            }
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+/// Returns the agent-check reply for the given status.
+pub fn response(status: &Status) -> String {
+    match (&status.phase, status.state) {
+        (DeploymentPhase::Maintenance, _) => "maint".to_string(),
+        (_, HealthState::Unhealthy) => {
+            if status.messages.is_empty() {
+                "down".to_string()
+            } else {
+                // The reply is a single line, so newlines in the messages are not allowed.
+                let reason = status.messages.join(", ").replace(['\r', '\n'], " ");
+                format!("down #{}", reason)
+            }
+        }
+        // Stop sending new traffic to the instance while it is being deployed or drained, but
+        // keep serving the existing connections.
+        (DeploymentPhase::Deploying | DeploymentPhase::Draining, HealthState::Healthy) => {
+            "drain".to_string()
+        }
+        (DeploymentPhase::Online, HealthState::Healthy) => "up 100%".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_response() {
+        let mut status = Status::new();
+        assert_eq!(response(&status), "up 100%");
+
+        status.set_phase(DeploymentPhase::Deploying);
+        assert_eq!(response(&status), "drain");
+
+        status.set_phase(DeploymentPhase::Draining);
+        assert_eq!(response(&status), "drain");
+
+        status.set_phase(DeploymentPhase::Maintenance);
+        assert_eq!(response(&status), "maint");
+
+        status.set_phase(DeploymentPhase::Deploying);
+        status.set_state(HealthState::Unhealthy);
+        assert_eq!(response(&status), "down");
+
+        status.add_message("Database\nunreachable".to_string());
+        assert_eq!(response(&status), "down #Database unreachable");
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, status.clone()));
+
+        let mut reply = String::new();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, "up 100%\n");
+
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let mut reply = String::new();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.read_to_string(&mut reply).await.unwrap();
+        assert_eq!(reply, "down\n");
+    }
+}
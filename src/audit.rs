@@ -0,0 +1,322 @@
+/// This module keeps an audit trail of the changes to the health state and the deployment phase,
+/// so it is possible to find out afterwards who took an instance out of rotation, and why. The
+/// most recent records are kept in memory for `GET /audit`, and every record can also be appended
+/// to a JSON Lines file, which is rotated when it grows too large.
+use crate::config::AuditConfig;
+use crate::status::{DeploymentPhase, HealthState};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// The number of records that are kept in memory.
+const RECENT_RECORDS: usize = 100;
+
+/// Who or what made a change.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Source {
+    /// A request that was made with the healthmonitor CLI.
+    Cli {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client: Option<IpAddr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// A request that was made by another API client.
+    Api {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client: Option<IpAddr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// A health check that failed.
+    Check { name: String },
+    /// The health monitor itself, e.g. the supervisor or the deployment timeout.
+    System { name: String },
+}
+
+impl Source {
+    /// Returns whether the change was requested through the API.
+    pub fn is_request(&self) -> bool {
+        matches!(self, Source::Cli { .. } | Source::Api { .. })
+    }
+}
+
+/// The value of a field before and after a change.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub source: Source,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<Change<HealthState>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<Change<DeploymentPhase>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// A JSON Lines file that is rotated by size: `audit.log` is renamed to `audit.log.1`,
+/// `audit.log.1` to `audit.log.2` and so on.
+struct AuditFile {
+    path: String,
+    max_size: u64,
+    files: usize,
+    file: File,
+    size: u64,
+}
+
+impl AuditFile {
+    fn open(path: &str, max_size: u64, files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditFile {
+            path: path.to_string(),
+            max_size,
+            files,
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.files).rev() {
+                let from = format!("{}.{}", self.path, index);
+                if fs::metadata(&from).is_ok() {
+                    fs::rename(&from, format!("{}.{}", self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Writes the lines it receives to the audit file on a thread of its own, so the file I/O and the
+/// rotation don't hold up the status, which is locked while a change is recorded.
+struct AuditWriter {
+    lines: Option<mpsc::Sender<String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AuditWriter {
+    fn start(mut file: AuditFile) -> io::Result<Self> {
+        let (lines, received) = mpsc::channel::<String>();
+        let thread = thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                for line in received {
+                    if let Err(e) = file.write(&line) {
+                        warn!("Failed to write the audit log {}: {}", file.path, e);
+                    }
+                }
+            })?;
+        Ok(AuditWriter {
+            lines: Some(lines),
+            thread: Some(thread),
+        })
+    }
+
+    fn write(&self, line: String) {
+        if let Some(lines) = &self.lines {
+            // The thread only stops when the writer is dropped.
+            let _ = lines.send(line);
+        }
+    }
+}
+
+impl Drop for AuditWriter {
+    /// Writes the remaining lines before the log is closed.
+    fn drop(&mut self) {
+        self.lines.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The audit log. Without a file it only keeps the recent records in memory.
+#[derive(Default)]
+pub struct AuditLog {
+    recent: VecDeque<AuditRecord>,
+    writer: Option<AuditWriter>,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Result<Self, String> {
+        let writer = match &config.file {
+            Some(path) => Some(
+                AuditFile::open(path, config.max_size, config.files)
+                    .and_then(AuditWriter::start)
+                    .map_err(|e| format!("Failed to open the audit log {}: {}", path, e))?,
+            ),
+            None => None,
+        };
+        Ok(AuditLog {
+            recent: VecDeque::new(),
+            writer,
+        })
+    }
+
+    /// Adds a record to the log. Failing to write the file doesn't stop the change from being
+    /// made, the record is still available in memory.
+    pub fn record(&mut self, record: AuditRecord) {
+        if let Some(writer) = &self.writer {
+            writer.write(serde_json::to_string(&record).unwrap_or_default() + "\n");
+        }
+        if self.recent.len() == RECENT_RECORDS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(record);
+    }
+
+    /// Stops writing records to the file, after the ones that are queued are written. They are
+    /// still kept in memory.
+    pub fn close(&mut self) {
+        self.writer.take();
+    }
+
+    /// Returns the recent records, oldest first.
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.recent.iter().cloned().collect()
+    }
+
+    /// Returns the source of the most recent change of the health state, if it is still known.
+    pub fn last_state_change(&self) -> Option<&Source> {
+        self.recent
+            .iter()
+            .rev()
+            .find(|record| record.state.is_some())
+            .map(|record| &record.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn record(message: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: Utc::now(),
+            request_id: None,
+            source: Source::Check {
+                name: "FileCheck".to_string(),
+            },
+            state: Some(Change {
+                old: HealthState::Healthy,
+                new: HealthState::Unhealthy,
+            }),
+            phase: None,
+            message: Some(message.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_recent_records() {
+        let mut log = AuditLog::default();
+        for i in 0..RECENT_RECORDS + 1 {
+            log.record(record(&i.to_string()));
+        }
+        let records = log.records();
+        assert_eq!(records.len(), RECENT_RECORDS);
+        assert_eq!(records[0].message.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log").to_string_lossy().to_string();
+        let line_size = serde_json::to_string(&record("0")).unwrap().len() as u64 + 1;
+        let config = AuditConfig {
+            file: Some(path.clone()),
+            max_size: line_size * 2,
+            files: 2,
+        };
+        let mut log = AuditLog::open(&config).unwrap();
+        for i in 0..7 {
+            log.record(record(&i.to_string()));
+        }
+        // Closing the log waits for the records to be written.
+        log.close();
+
+        let read = |path: String| -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let record: AuditRecord = serde_json::from_str(line).unwrap();
+                    record.message.unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(read(path.clone()), ["6"]);
+        assert_eq!(read(format!("{}.1", path)), ["4", "5"]);
+        assert_eq!(read(format!("{}.2", path)), ["2", "3"]);
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+
+        // Records are appended to an existing file.
+        let mut log = AuditLog::open(&config).unwrap();
+        log.record(record("7"));
+        drop(log);
+        assert_eq!(read(path), ["6", "7"]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let record = AuditRecord {
+            request_id: Some("abc".to_string()),
+            source: Source::Api {
+                client: Some("10.0.0.1".parse().unwrap()),
+                token: Some("deploy".to_string()),
+            },
+            state: None,
+            phase: Some(Change {
+                old: DeploymentPhase::Online,
+                new: DeploymentPhase::Deploying,
+            }),
+            message: None,
+            ..record("")
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["request_id"], "abc");
+        assert_eq!(json["source"]["type"], "api");
+        assert_eq!(json["source"]["client"], "10.0.0.1");
+        assert_eq!(json["source"]["token"], "deploy");
+        assert_eq!(json["phase"]["old"], "online");
+        assert_eq!(json["phase"]["new"], "deploying");
+        assert!(json.get("state").is_none());
+        assert!(json.get("message").is_none());
+    }
+}
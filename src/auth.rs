@@ -0,0 +1,234 @@
+/// This module implements the optional bearer token authentication of the REST API. Every token
+/// has a role: `read` tokens can query the status, `write` tokens can also change it. When no
+/// tokens are configured the API is open, as before.
+use crate::config::AuthConfig;
+
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::debug;
+use std::fmt;
+use std::sync::Arc;
+
+/// The probes are used by orchestrators that can't always send credentials, and only tell whether
+/// they pass to clients without a token. The dashboard is a static page, it sends the token itself
+/// when it requests the status.
+const PUBLIC_PATHS: [&str; 5] = ["/livez", "/readyz", "/startupz", "/", "/dashboard"];
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Role {
+    Read,
+    Write,
+}
+
+impl TryFrom<&str> for Role {
+    type Error = &'static str;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "read" => Ok(Role::Read),
+            "write" => Ok(Role::Write),
+            _ => Err("Invalid role"),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let role = match self {
+            Role::Read => "read",
+            Role::Write => "write",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+/// An API token. The ID identifies the token in the logs without revealing the secret.
+#[derive(Clone, PartialEq)]
+pub struct Token {
+    pub id: String,
+    pub role: Role,
+    secret: String,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("id", &self.id)
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+impl TryFrom<&str> for Token {
+    type Error = String;
+    /// Parses a token in the form `id:role:secret`.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut parts = value.trim().splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(id), Some(role), Some(secret)) if !id.is_empty() && !secret.is_empty() => {
+                Ok(Token {
+                    id: id.to_string(),
+                    role: Role::try_from(role)
+                        .map_err(|_| format!("Invalid role {} for token {}.", role, id))?,
+                    secret: secret.to_string(),
+                })
+            }
+            _ => Err("Tokens should be in the form id:role:secret.".to_string()),
+        }
+    }
+}
+
+/// The tokens that give access to the API.
+#[derive(Clone, Debug, Default)]
+pub struct Tokens(Vec<Token>);
+
+impl Tokens {
+    /// Loads the tokens from the environment and the tokens file. The tokens file has a token per
+    /// line, empty lines and lines starting with `#` are ignored.
+    pub fn load(config: &AuthConfig) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        for token in &config.tokens {
+            tokens.push(Token::try_from(token.as_str())?);
+        }
+        if let Some(path) = &config.tokens_file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read the tokens file {}: {}", path, e))?;
+            for line in contents.lines() {
+                let line = line.trim();
+                if !line.is_empty() && !line.starts_with('#') {
+                    tokens.push(Token::try_from(line)?);
+                }
+            }
+        }
+        Ok(Tokens(tokens))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the token with the given secret.
+    fn find(&self, secret: &str) -> Option<&Token> {
+        self.0
+            .iter()
+            .find(|token| constant_time_eq(token.secret.as_bytes(), secret.as_bytes()))
+    }
+}
+
+/// Compares two secrets in a time that doesn't depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the role needed for a request: requests that change something need the write role.
+pub fn required_role(method: &Method) -> Role {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => Role::Read,
+        _ => Role::Write,
+    }
+}
+
+/// Marks a request for a public path that came without a valid token, while tokens are required.
+#[derive(Clone, Copy, Debug)]
+pub struct Anonymous;
+
+/// Middleware that checks the bearer token of a request. The token is added to the request
+/// extensions, so handlers know who made the request. Requests for the public paths are let
+/// through without one, and marked as [`Anonymous`].
+pub async fn authenticate(
+    State(tokens): State<Arc<Tokens>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if tokens.is_empty() {
+        return next.run(request).await;
+    }
+
+    let secret = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let token = secret.and_then(|secret| tokens.find(secret.trim()));
+    if PUBLIC_PATHS.contains(&request.uri().path()) {
+        match token {
+            Some(token) => {
+                request.extensions_mut().insert(token.clone());
+            }
+            None => {
+                request.extensions_mut().insert(Anonymous);
+            }
+        }
+        return next.run(request).await;
+    }
+    let Some(token) = token else {
+        debug!("Missing or invalid token for {}", request.uri());
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "A valid token is required.",
+        )
+            .into_response();
+    };
+
+    let required = required_role(request.method());
+    if token.role < required {
+        debug!(
+            "Token {} has the {} role, {} needs {}",
+            token.id,
+            token.role,
+            request.uri(),
+            required
+        );
+        let message = format!("This request needs a token with the {} role.", required);
+        return (StatusCode::FORBIDDEN, message).into_response();
+    }
+
+    request.extensions_mut().insert(token.clone());
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_token() {
+        let token = Token::try_from("deploy:write:s3cr3t:with:colons").unwrap();
+        assert_eq!(token.id, "deploy");
+        assert_eq!(token.role, Role::Write);
+        assert_eq!(token.secret, "s3cr3t:with:colons");
+
+        assert!(Token::try_from("deploy:admin:s3cr3t").is_err());
+        assert!(Token::try_from("s3cr3t").is_err());
+        assert!(Token::try_from("deploy:read:").is_err());
+    }
+
+    #[test]
+    fn test_load_tokens() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            "# Tokens\nhaproxy:read:abc\n\n  deploy:write:def\n",
+        )
+        .unwrap();
+        let config = AuthConfig {
+            tokens: vec!["cli:write:ghi".to_string()],
+            tokens_file: Some(file.path().to_string_lossy().to_string()),
+            token: None,
+        };
+        let tokens = Tokens::load(&config).unwrap();
+        assert_eq!(tokens.find("abc").unwrap().id, "haproxy");
+        assert_eq!(tokens.find("def").unwrap().role, Role::Write);
+        assert_eq!(tokens.find("ghi").unwrap().id, "cli");
+        assert!(tokens.find("ab").is_none());
+
+        let config = AuthConfig {
+            tokens: vec![],
+            tokens_file: Some("/nonexistent/tokens".to_string()),
+            token: None,
+        };
+        assert!(Tokens::load(&config).is_err());
+    }
+}
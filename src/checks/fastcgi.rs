@@ -0,0 +1,149 @@
+/// A minimal FastCGI client, just enough to request a single page from a responder such as
+/// php-fpm. See https://fastcgi-archives.github.io/FastCGI_Specification.html
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 1;
+const REQUEST_ID: u16 = 1;
+const ROLE_RESPONDER: u16 = 1;
+
+pub const BEGIN_REQUEST: u8 = 1;
+pub const END_REQUEST: u8 = 3;
+pub const PARAMS: u8 = 4;
+pub const STDIN: u8 = 5;
+pub const STDOUT: u8 = 6;
+pub const STDERR: u8 = 7;
+
+/// The response of a FastCGI responder: the status code from its CGI headers, which is 200 when
+/// they have no `Status` header, and the body.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Sends a request with the given CGI parameters over the stream and returns the response.
+pub async fn request<S>(stream: &mut S, params: &[(&str, &str)]) -> Result<Response, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&ROLE_RESPONDER.to_be_bytes());
+    begin.extend_from_slice(&[0; 6]);
+
+    let mut encoded_params = Vec::new();
+    for (name, value) in params {
+        encode_length(&mut encoded_params, name.len());
+        encode_length(&mut encoded_params, value.len());
+        encoded_params.extend_from_slice(name.as_bytes());
+        encoded_params.extend_from_slice(value.as_bytes());
+    }
+
+    let mut request = Vec::new();
+    write_record(&mut request, BEGIN_REQUEST, &begin);
+    for chunk in encoded_params.chunks(u16::MAX as usize) {
+        write_record(&mut request, PARAMS, chunk);
+    }
+    // Empty PARAMS and STDIN records mark the end of the respective streams.
+    write_record(&mut request, PARAMS, &[]);
+    write_record(&mut request, STDIN, &[]);
+
+    stream
+        .write_all(&request)
+        .await
+        .map_err(|e| format!("Failed to send FastCGI request: {}", e))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to send FastCGI request: {}", e))?;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    loop {
+        let (record_type, content) = read_record(stream).await?;
+        match record_type {
+            STDOUT => stdout.extend_from_slice(&content),
+            STDERR => stderr.extend_from_slice(&content),
+            END_REQUEST => break,
+            _ => {}
+        }
+    }
+
+    if stdout.is_empty() && !stderr.is_empty() {
+        return Err(format!(
+            "FastCGI error: {}",
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+
+    parse_response(&String::from_utf8_lossy(&stdout))
+}
+
+/// Appends a record of the given type to the buffer.
+pub fn write_record(buffer: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+    buffer.push(VERSION);
+    buffer.push(record_type);
+    buffer.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    buffer.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    // No padding, and a reserved byte.
+    buffer.extend_from_slice(&[0, 0]);
+    buffer.extend_from_slice(content);
+}
+
+/// Reads a single record from the stream, returning its type and content.
+pub async fn read_record<S>(stream: &mut S) -> Result<(u8, Vec<u8>), String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 8];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| format!("Failed to read FastCGI response: {}", e))?;
+    if header[0] != VERSION {
+        return Err(format!("Unsupported FastCGI version {}", header[0]));
+    }
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding_length = header[6] as usize;
+    let mut content = vec![0u8; content_length + padding_length];
+    stream
+        .read_exact(&mut content)
+        .await
+        .map_err(|e| format!("Failed to read FastCGI response: {}", e))?;
+    content.truncate(content_length);
+    Ok((header[1], content))
+}
+
+fn encode_length(buffer: &mut Vec<u8>, length: usize) {
+    if length < 128 {
+        buffer.push(length as u8);
+    } else {
+        buffer.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// Splits the CGI output in headers and body, and extracts the status code.
+fn parse_response(output: &str) -> Result<Response, String> {
+    let (headers, body) = output
+        .split_once("\r\n\r\n")
+        .or_else(|| output.split_once("\n\n"))
+        .ok_or_else(|| "Invalid FastCGI response: missing headers".to_string())?;
+
+    let mut status = 200;
+    for header in headers.lines() {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("status") {
+                status = value
+                    .trim()
+                    .split(' ')
+                    .next()
+                    .and_then(|code| code.parse().ok())
+                    .ok_or_else(|| format!("Invalid FastCGI status header: {}", value.trim()))?;
+            }
+        }
+    }
+
+    Ok(Response {
+        status,
+        body: body.to_string(),
+    })
+}
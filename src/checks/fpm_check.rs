@@ -0,0 +1,365 @@
+use crate::checks::fastcgi;
+use crate::checks::HealthCheck;
+use crate::config::Config;
+use async_trait::async_trait;
+use log::debug;
+use std::time::Duration;
+use tokio::net::{TcpStream, UnixStream};
+
+pub struct FpmCheck {
+    name: &'static str,
+    interval: usize,
+    timeout: usize,
+    is_quick_check: bool,
+    addresses: Vec<String>,
+    status_path: String,
+    max_listen_queue: Option<usize>,
+    max_saturation: Option<usize>,
+}
+
+/// The process manager statistics of a php-fpm pool, as reported on its status page.
+#[derive(Debug, Default, PartialEq)]
+struct PoolStatus {
+    listen_queue: usize,
+    idle_processes: usize,
+    active_processes: usize,
+    total_processes: usize,
+    max_children_reached: usize,
+}
+
+impl PoolStatus {
+    /// Parses the plain text output of the php-fpm status page.
+    fn parse(body: &str) -> Result<Self, String> {
+        let mut status = PoolStatus::default();
+        let mut found = 0;
+        for line in body.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let field = match key.trim() {
+                "listen queue" => &mut status.listen_queue,
+                "idle processes" => &mut status.idle_processes,
+                "active processes" => &mut status.active_processes,
+                "total processes" => &mut status.total_processes,
+                "max children reached" => &mut status.max_children_reached,
+                _ => continue,
+            };
+            *field = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid value for '{}': {}", key.trim(), value.trim()))?;
+            found += 1;
+        }
+        if found < 5 {
+            return Err("Status page does not contain the php-fpm pool statistics".to_string());
+        }
+        Ok(status)
+    }
+
+    /// The percentage of processes that are busy handling requests.
+    fn saturation(&self) -> usize {
+        if self.total_processes == 0 {
+            return 0;
+        }
+        self.active_processes * 100 / self.total_processes
+    }
+}
+
+impl FpmCheck {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            name: "FpmCheck",
+            interval: config.checks.fpm_check.interval,
+            timeout: config.checks.fpm_check.timeout,
+            is_quick_check: false,
+            addresses: config.checks.fpm_check.addresses.clone(),
+            status_path: config.checks.fpm_check.status_path.clone(),
+            max_listen_queue: config.checks.fpm_check.max_listen_queue,
+            max_saturation: config.checks.fpm_check.max_saturation,
+        }
+    }
+
+    /// Requests the status page from the php-fpm pool listening on the given address. Addresses
+    /// starting with `unix:` or `/` are Unix sockets, anything else is a TCP `host:port`.
+    async fn fetch_status(&self, address: &str) -> Result<PoolStatus, String> {
+        let params = [
+            ("GATEWAY_INTERFACE", "CGI/1.1"),
+            ("REQUEST_METHOD", "GET"),
+            ("SCRIPT_NAME", self.status_path.as_str()),
+            ("SCRIPT_FILENAME", self.status_path.as_str()),
+            ("REQUEST_URI", self.status_path.as_str()),
+            ("QUERY_STRING", ""),
+            ("SERVER_PROTOCOL", "HTTP/1.1"),
+            ("SERVER_SOFTWARE", env!("CARGO_PKG_NAME")),
+        ];
+
+        let response = if let Some(path) = unix_socket_path(address) {
+            let mut stream = UnixStream::connect(path)
+                .await
+                .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+            fastcgi::request(&mut stream, &params).await?
+        } else {
+            let mut stream = TcpStream::connect(address)
+                .await
+                .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+            fastcgi::request(&mut stream, &params).await?
+        };
+
+        if response.status != 200 {
+            return Err(format!(
+                "Status page {} on {} returned status {}",
+                self.status_path, address, response.status
+            ));
+        }
+        PoolStatus::parse(&response.body).map_err(|e| format!("{}: {}", address, e))
+    }
+
+    /// Checks the pool statistics against the configured thresholds.
+    fn validate(&self, address: &str, status: &PoolStatus) -> Result<(), String> {
+        if let Some(max_listen_queue) = self.max_listen_queue {
+            if status.listen_queue > max_listen_queue {
+                return Err(format!(
+                    "Listen queue of {} is {}, exceeding the maximum of {} (max children reached {} times)",
+                    address, status.listen_queue, max_listen_queue, status.max_children_reached
+                ));
+            }
+        }
+        if let Some(max_saturation) = self.max_saturation {
+            if status.saturation() > max_saturation {
+                return Err(format!(
+                    "Pool {} is {}% saturated ({} of {} processes active), exceeding the maximum of {}% (max children reached {} times)",
+                    address,
+                    status.saturation(),
+                    status.active_processes,
+                    status.total_processes,
+                    max_saturation,
+                    status.max_children_reached
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the socket path if the address refers to a Unix socket.
+fn unix_socket_path(address: &str) -> Option<&str> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        Some(path)
+    } else if address.starts_with('/') {
+        Some(address)
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl HealthCheck for FpmCheck {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn interval(&self) -> usize {
+        self.interval
+    }
+
+    fn is_quick_check(&self) -> bool {
+        self.is_quick_check
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.addresses.is_empty()
+    }
+
+    async fn run(&self) -> Result<(), String> {
+        debug!("Running php-fpm checks");
+
+        for address in &self.addresses {
+            let status = tokio::time::timeout(
+                Duration::from_secs(self.timeout as u64),
+                self.fetch_status(address),
+            )
+            .await
+            .map_err(|_| format!("Timed out requesting the status of {}", address))??;
+            debug!("php-fpm status of {}: {:?}", address, status);
+            self.validate(address, &status)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, UnixListener};
+
+    const STATUS_PAGE: &str = "pool:                 www
+process manager:      dynamic
+start time:           18/Oct/2026:10:00:00 +0000
+start since:          3600
+accepted conn:        1234
+listen queue:         LISTEN_QUEUE
+max listen queue:     3
+listen queue len:     511
+idle processes:       IDLE
+active processes:     ACTIVE
+total processes:      TOTAL
+max active processes: 5
+max children reached: 0
+slow requests:        0
+";
+
+    fn status_page(listen_queue: usize, active: usize, total: usize) -> String {
+        STATUS_PAGE
+            .replace("LISTEN_QUEUE", &listen_queue.to_string())
+            .replace("IDLE", &(total - active).to_string())
+            .replace("ACTIVE", &active.to_string())
+            .replace("TOTAL", &total.to_string())
+    }
+
+    /// Handles a single FastCGI request by responding with the given CGI output.
+    async fn respond<S>(mut stream: S, output: String)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // Read until the end of the (empty) STDIN stream.
+        loop {
+            let (record_type, content) = fastcgi::read_record(&mut stream).await.unwrap();
+            if record_type == fastcgi::STDIN && content.is_empty() {
+                break;
+            }
+        }
+        let mut response = Vec::new();
+        fastcgi::write_record(&mut response, fastcgi::STDOUT, output.as_bytes());
+        fastcgi::write_record(&mut response, fastcgi::STDOUT, &[]);
+        fastcgi::write_record(&mut response, fastcgi::END_REQUEST, &[0; 8]);
+        stream.write_all(&response).await.unwrap();
+    }
+
+    /// Starts an in-process FastCGI responder on a random TCP port that serves the given output.
+    async fn tcp_responder(output: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(respond(stream, output.clone()));
+            }
+        });
+        address
+    }
+
+    /// Starts an in-process FastCGI responder on a Unix socket that serves the given output.
+    async fn unix_responder(dir: &TempDir, output: String) -> String {
+        let path = dir.path().join("php-fpm.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(respond(stream, output.clone()));
+            }
+        });
+        format!("unix:{}", path.display())
+    }
+
+    fn ok_output(body: String) -> String {
+        format!("Content-type: text/plain;charset=UTF-8\r\n\r\n{}", body)
+    }
+
+    fn check_for(addresses: Vec<String>) -> FpmCheck {
+        let mut config = Config::new();
+        config.checks.fpm_check.addresses = addresses;
+        FpmCheck::new(&config)
+    }
+
+    #[test]
+    fn test_name() {
+        let check = FpmCheck::new(&Config::new());
+        assert_eq!(check.name(), "FpmCheck");
+    }
+
+    #[test]
+    fn test_is_quick_check() {
+        let check = FpmCheck::new(&Config::new());
+        assert!(!check.is_quick_check());
+    }
+
+    #[test]
+    fn test_without_addresses_is_disabled() {
+        let check = check_for(vec![]);
+        assert!(!check.is_enabled());
+    }
+
+    #[test]
+    fn test_parse_status_page() {
+        let status = PoolStatus::parse(&status_page(2, 3, 4)).unwrap();
+        assert_eq!(
+            status,
+            PoolStatus {
+                listen_queue: 2,
+                idle_processes: 1,
+                active_processes: 3,
+                total_processes: 4,
+                max_children_reached: 0,
+            }
+        );
+        assert_eq!(status.saturation(), 75);
+
+        assert!(PoolStatus::parse("File not found.").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_over_tcp() {
+        let address = tcp_responder(ok_output(status_page(0, 1, 4))).await;
+        let check = check_for(vec![address]);
+        assert!(check.run().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_over_unix_socket() {
+        let dir = TempDir::new().unwrap();
+        let address = unix_responder(&dir, ok_output(status_page(0, 1, 4))).await;
+        let check = check_for(vec![address]);
+        assert!(check.run().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_with_listen_queue_exceeded() {
+        let address = tcp_responder(ok_output(status_page(5, 1, 4))).await;
+        let mut check = check_for(vec![address]);
+        assert!(check.run().await.is_ok());
+
+        check.max_listen_queue = Some(5);
+        assert!(check.run().await.is_ok());
+
+        check.max_listen_queue = Some(4);
+        let error = check.run().await.unwrap_err();
+        assert!(error.contains("Listen queue"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_run_with_saturation_exceeded() {
+        let address = tcp_responder(ok_output(status_page(0, 4, 5))).await;
+        let mut check = check_for(vec![address]);
+        check.max_saturation = Some(80);
+        assert!(check.run().await.is_ok());
+
+        check.max_saturation = Some(75);
+        let error = check.run().await.unwrap_err();
+        assert!(error.contains("80% saturated"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_run_with_status_page_not_found() {
+        let output = "Status: 404 Not Found\r\nContent-type: text/html\r\n\r\nFile not found.";
+        let address = tcp_responder(output.to_string()).await;
+        let check = check_for(vec![address]);
+        let error = check.run().await.unwrap_err();
+        assert!(error.contains("returned status 404"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_run_without_responder() {
+        let check = check_for(vec!["127.0.0.1:1".to_string()]);
+        assert!(check.run().await.is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::checks::HealthCheck;
+use crate::config::Config;
+use async_trait::async_trait;
+use log::debug;
+use std::time::Duration;
+use tonic::transport::Endpoint;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+
+pub struct GrpcCheck {
+    name: &'static str,
+    interval: usize,
+    timeout: usize,
+    is_quick_check: bool,
+    targets: Vec<String>,
+    service: String,
+}
+
+impl GrpcCheck {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            name: "GrpcCheck",
+            interval: config.checks.grpc_check.interval,
+            timeout: config.checks.grpc_check.timeout,
+            is_quick_check: false,
+            targets: config.checks.grpc_check.targets.clone(),
+            service: config.checks.grpc_check.service.clone(),
+        }
+    }
+
+    /// Calls the health checking service on the given target and returns the reported status.
+    async fn check_target(&self, target: &str) -> Result<ServingStatus, String> {
+        let uri = if target.contains("://") {
+            target.to_string()
+        } else {
+            format!("http://{}", target)
+        };
+        let timeout = Duration::from_secs(self.timeout as u64);
+        let channel = Endpoint::from_shared(uri)
+            .map_err(|e| format!("Invalid gRPC target {}: {}", target, e))?
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .connect()
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", target, e))?;
+
+        let request = HealthCheckRequest {
+            service: self.service.clone(),
+        };
+        let response = HealthClient::new(channel)
+            .check(request)
+            .await
+            .map_err(|e| format!("Health check on {} failed: {}", target, e.message()))?;
+
+        Ok(ServingStatus::try_from(response.into_inner().status).unwrap_or(ServingStatus::Unknown))
+    }
+}
+
+#[async_trait]
+impl HealthCheck for GrpcCheck {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn interval(&self) -> usize {
+        self.interval
+    }
+
+    fn is_quick_check(&self) -> bool {
+        self.is_quick_check
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    async fn run(&self) -> Result<(), String> {
+        debug!("Running gRPC checks");
+
+        for target in &self.targets {
+            let status = self.check_target(target).await?;
+            if status != ServingStatus::Serving {
+                return Err(format!(
+                    "gRPC target {} reports status {}",
+                    target,
+                    status.as_str_name()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::server::{health_reporter, HealthReporter};
+
+    /// Starts a local gRPC server exposing the standard health service on a random port.
+    async fn start_health_server() -> (String, HealthReporter) {
+        let (reporter, service) = health_reporter();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+        (address, reporter)
+    }
+
+    fn check_for(targets: Vec<String>, service: &str) -> GrpcCheck {
+        let mut config = Config::new();
+        config.checks.grpc_check.targets = targets;
+        config.checks.grpc_check.service = service.to_string();
+        config.checks.grpc_check.timeout = 2;
+        GrpcCheck::new(&config)
+    }
+
+    #[test]
+    fn test_name() {
+        let check = GrpcCheck::new(&Config::new());
+        assert_eq!(check.name(), "GrpcCheck");
+    }
+
+    #[test]
+    fn test_is_quick_check() {
+        let check = GrpcCheck::new(&Config::new());
+        assert!(!check.is_quick_check());
+    }
+
+    #[test]
+    fn test_without_targets_is_disabled() {
+        let check = check_for(vec![], "");
+        assert!(!check.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_run_with_serving_server() {
+        let (address, _reporter) = start_health_server().await;
+        let check = check_for(vec![address.clone()], "");
+        assert!(check.run().await.is_ok());
+
+        let check = check_for(vec![format!("http://{}", address)], "");
+        assert!(check.run().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_with_service() {
+        let (address, reporter) = start_health_server().await;
+        reporter
+            .set_service_status("example.Backend", tonic_health::ServingStatus::Serving)
+            .await;
+        let check = check_for(vec![address.clone()], "example.Backend");
+        assert!(check.run().await.is_ok());
+
+        reporter
+            .set_service_status("example.Backend", tonic_health::ServingStatus::NotServing)
+            .await;
+        let error = check.run().await.unwrap_err();
+        assert!(error.contains("NOT_SERVING"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_run_with_unknown_service() {
+        let (address, _reporter) = start_health_server().await;
+        let check = check_for(vec![address], "example.Unknown");
+        assert!(check.run().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_without_server() {
+        let check = check_for(vec!["127.0.0.1:1".to_string()], "");
+        assert!(check.run().await.is_err());
+    }
+}
@@ -1,4 +1,7 @@
+mod fastcgi;
 mod file_check;
+mod fpm_check;
+mod grpc_check;
 mod url_check;
 
 pub mod plugin_manager;
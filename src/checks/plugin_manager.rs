@@ -1,11 +1,21 @@
+use crate::audit::Source;
 use crate::checks::file_check::FileCheck;
+use crate::checks::fpm_check::FpmCheck;
+use crate::checks::grpc_check::GrpcCheck;
 use crate::checks::url_check::UrlCheck;
 use crate::checks::HealthCheck;
-use crate::config::CONFIG;
+use crate::config::{Config, CONFIG};
 use crate::status::{DeploymentPhase, Status};
-use log::{debug, error};
+use chrono::Utc;
+use log::{debug, error, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+
+/// How long to wait for running checks to finish when stopping.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct PluginManager {
     plugins: Vec<Arc<dyn HealthCheck + Send + Sync>>,
@@ -13,37 +23,61 @@ pub struct PluginManager {
 
 impl PluginManager {
     pub fn new() -> Self {
+        Self::with_config(&CONFIG)
+    }
+
+    /// Creates the checks from the given configuration, e.g. after it has been reloaded.
+    pub fn with_config(config: &Config) -> Self {
         Self {
-            plugins: create_plugins(),
+            plugins: create_plugins(config),
         }
     }
 
-    /// Starts health monitoring by running checks in a loop.
-    pub async fn monitor(&self, status: Arc<Mutex<Status>>) {
+    /// Starts health monitoring by running checks in a loop. Returns a handle to stop them.
+    pub async fn monitor(&self, status: Arc<Mutex<Status>>) -> Checks {
+        let (shutdown, _) = watch::channel(false);
+        let mut handles = Vec::new();
         debug!("Running checks");
+        // The number of checks that have not yet completed their first run.
+        let pending = Arc::new(AtomicUsize::new(self.plugins.len()));
+        if self.plugins.is_empty() {
+            status.lock().await.complete_startup();
+        }
+
         for plugin in &self.plugins {
             let plugin = Arc::clone(plugin);
             debug!("Initializing loop for {}", plugin.name());
             let status = status.clone();
+            let pending = pending.clone();
             let interval = plugin.interval();
             let plugin_name = plugin.name().to_string();
-            tokio::spawn(async move {
+            let mut stopped = shutdown.subscribe();
+            handles.push(tokio::spawn(async move {
+                let mut first_run = true;
                 loop {
                     if !plugin.is_enabled() {
                         debug!("{} is disabled. Skipping.", plugin_name);
+                        if finish_first_run(&pending, &mut first_run) {
+                            status.lock().await.complete_startup();
+                        }
                         break;
                     }
 
-                    // If the application is in the deployment phase, wait until we are online.
-                    let current_status = status.lock().await;
-                    if current_status.phase == DeploymentPhase::Deploying {
+                    // If the application is being deployed, drained or is in maintenance, wait
+                    // until we are online.
+                    let mut current_status = status.lock().await;
+                    current_status.unschedule_check(&plugin_name);
+                    if current_status.phase != DeploymentPhase::Online {
                         debug!(
-                            "Not executing {} because the application is being deployed",
-                            plugin_name
+                            "Not executing {} because the application is {}",
+                            plugin_name, current_status.phase
                         );
+                        let mut changes = current_status.subscribe();
                         drop(current_status);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                        continue;
+                        tokio::select! {
+                            _ = changes.changed() => continue,
+                            _ = stopped.changed() => break,
+                        }
                     }
 
                     // Only run the check if the status is healthy.
@@ -52,23 +86,49 @@ impl PluginManager {
                             "Not executing {} because the status is unhealthy",
                             plugin_name
                         );
+                        if finish_first_run(&pending, &mut first_run) {
+                            current_status.complete_startup();
+                        }
                         break;
                     }
+                    current_status.start_check(&plugin_name);
                     drop(current_status);
 
-                    if let Err(e) = plugin.run().await {
-                        status.lock().await.state = crate::status::HealthState::Unhealthy;
+                    let result = plugin.run().await;
+                    let mut current_status = status.lock().await;
+                    current_status.set_check_result(&plugin_name, &result);
+                    if finish_first_run(&pending, &mut first_run) {
+                        current_status.complete_startup();
+                    }
+                    if let Err(e) = result {
+                        let before = current_status.snapshot();
+                        current_status.set_state(crate::status::HealthState::Unhealthy);
                         let message: String = format!("{}: {}", plugin_name, e).to_string();
-                        status.lock().await.add_message(message);
+                        current_status.add_message(message);
+                        let source = Source::Check {
+                            name: plugin_name.clone(),
+                        };
+                        current_status.audit(before, source, None);
                         error!("{} failed: {}", plugin_name, e);
                         break;
                     }
+                    drop(current_status);
 
                     debug!("{} succeeded", plugin_name);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(interval as u64)).await;
+                    let due = Utc::now() + chrono::Duration::seconds(interval as i64);
+                    status.lock().await.schedule_check(&plugin_name, due);
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(interval as u64)) => {}
+                        _ = stopped.changed() => {
+                            status.lock().await.unschedule_check(&plugin_name);
+                            break;
+                        }
+                    }
                 }
-            });
+            }));
         }
+
+        Checks { handles, shutdown }
     }
 
     /// Runs all the quick checks once.
@@ -83,9 +143,44 @@ impl PluginManager {
     }
 }
 
-fn create_plugins() -> Vec<Arc<dyn HealthCheck + Send + Sync>> {
+/// The running health checks. They stop when this is dropped.
+#[must_use = "the checks stop when the handle is dropped"]
+pub struct Checks {
+    handles: Vec<JoinHandle<()>>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Checks {
+    /// Stops the checks, waiting for the checks that are running to finish.
+    pub async fn stop(self) {
+        self.shutdown.send_replace(true);
+        for mut handle in self.handles {
+            if tokio::time::timeout(STOP_TIMEOUT, &mut handle)
+                .await
+                .is_err()
+            {
+                warn!("A health check did not finish in time, aborting it.");
+                handle.abort();
+            }
+        }
+        debug!("Health checks stopped.");
+    }
+}
+
+/// Marks the first run of a check as finished. Returns true if this was the last check to finish
+/// its first run, meaning the first round of checks has completed.
+fn finish_first_run(pending: &AtomicUsize, first_run: &mut bool) -> bool {
+    if !std::mem::take(first_run) {
+        return false;
+    }
+    pending.fetch_sub(1, Ordering::SeqCst) == 1
+}
+
+fn create_plugins(config: &Config) -> Vec<Arc<dyn HealthCheck + Send + Sync>> {
     vec![
-        Arc::new(FileCheck::new(&CONFIG)),
-        Arc::new(UrlCheck::new(&CONFIG)),
+        Arc::new(FileCheck::new(config)),
+        Arc::new(UrlCheck::new(config)),
+        Arc::new(FpmCheck::new(config)),
+        Arc::new(GrpcCheck::new(config)),
     ]
 }
@@ -1,5 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(name = "healthmonitor")]
@@ -28,6 +29,34 @@ pub enum Commands {
     },
     /// Performs a quick health check of the server environment.
     Check,
+    /// Runs a deployment command, putting the application online if it succeeds.
+    ///
+    /// Example: healthmonitor deploy --version 1.2.0 -- drush deploy
+    Deploy {
+        #[command(flatten)]
+        release: ReleaseArgs,
+        /// The deployment command and its arguments.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Starts the web server and runs the monitored application, reporting it as unhealthy when it
+    /// exits.
+    ///
+    /// Example: healthmonitor run --restart on-failure -- php-fpm --nodaemonize
+    Run {
+        /// When to restart the application after it exits: never, on-failure or always.
+        #[arg(long, default_value = "never")]
+        restart: RestartPolicy,
+        /// The maximum number of restarts. Unlimited if omitted.
+        #[arg(long)]
+        max_restarts: Option<u32>,
+        /// The delay before the first restart in seconds. It doubles on every consecutive restart.
+        #[arg(long, default_value_t = 1)]
+        backoff: u64,
+        /// The application command and its arguments.
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -51,6 +80,16 @@ pub enum StateCommands {
         #[arg(long)]
         message: Option<String>,
     },
+    /// Waits until the monitored application has the given health state.
+    ///
+    /// Exits with 0 when it does, 124 when the timeout expires first, and 1 on errors.
+    Wait {
+        /// The health state to wait for: healthy or unhealthy.
+        health_state: HealthState,
+        /// How long to wait, e.g. 30s, 5m or 1h.
+        #[arg(long, default_value = "5m", value_parser = crate::wait::parse_duration)]
+        timeout: Duration,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -78,15 +117,55 @@ pub enum PhaseCommands {
     Get,
     /// Sets the deployment phase of the monitored application.
     Set {
-        /// The new deployment phase of the monitored application.
+        /// The new deployment phase of the monitored application: deploying, online, draining or
+        /// maintenance.
+        phase: DeploymentPhase,
+        /// A message describing the phase change, e.g. the reason for maintenance.
+        #[arg(long)]
+        message: Option<String>,
+        #[command(flatten)]
+        release: ReleaseArgs,
+        /// The deployment step that is currently being executed, e.g. "database updates".
+        #[arg(long)]
+        step: Option<String>,
+        /// The progress of the deployment as a percentage.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        progress: Option<u8>,
+    },
+    /// Waits until the monitored application is in the given deployment phase.
+    ///
+    /// Exits with 0 when it is, 124 when the timeout expires first, and 1 on errors.
+    Wait {
+        /// The deployment phase to wait for: deploying, online, draining or maintenance.
         phase: DeploymentPhase,
+        /// How long to wait, e.g. 30s, 5m or 1h.
+        #[arg(long, default_value = "5m", value_parser = crate::wait::parse_duration)]
+        timeout: Duration,
     },
+    /// Shows the running release, the deployment in progress and the past deployments.
+    History,
+}
+
+/// Describes the release that is being deployed.
+#[derive(Args, Debug)]
+pub struct ReleaseArgs {
+    /// The version of the release that is being deployed.
+    #[arg(long)]
+    pub version: Option<String>,
+    /// The git commit SHA of the release that is being deployed.
+    #[arg(long)]
+    pub sha: Option<String>,
+    /// The person or system performing the deployment.
+    #[arg(long)]
+    pub deployer: Option<String>,
 }
 
 #[derive(Clone, Debug)]
 pub enum DeploymentPhase {
     Deploying,
     Online,
+    Draining,
+    Maintenance,
 }
 
 impl FromStr for DeploymentPhase {
@@ -96,7 +175,29 @@ impl FromStr for DeploymentPhase {
         match s.to_lowercase().as_str() {
             "deploying" => Ok(DeploymentPhase::Deploying),
             "online" => Ok(DeploymentPhase::Online),
+            "draining" => Ok(DeploymentPhase::Draining),
+            "maintenance" => Ok(DeploymentPhase::Maintenance),
             _ => Err(format!("Invalid phase: {}", s)),
         }
     }
 }
+
+#[derive(Clone, Debug)]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!("Invalid restart policy: {}", s)),
+        }
+    }
+}
@@ -1,39 +1,170 @@
-use crate::config::CONFIG;
-use crate::status::{DeploymentPhase, HealthState, Status};
-/// This module contains an HTTP client that queries our own server.
+/// This module contains a typed client for the REST API of our own server. The requests and
+/// responses are (de)serialized with the same types the server uses, so the CLI and the server
+/// can't disagree about the wire format.
+use crate::config::{TlsConfig, CONFIG};
+use crate::problem::Problem;
+use crate::server::Info;
+use crate::status::{
+    DeploymentPhase, DeploymentUpdate, Deployments, HealthState, Status, StatusPatch,
+};
+use crate::wait::Condition;
 use std::fmt::Debug;
+use std::time::{Duration, Instant};
 
 use log::debug;
-use reqwest::Client;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
+/// The user agent of the CLI, e.g. "healthmonitor/0.1.0".
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The longest a single wait request is held by the server.
+const WAIT_REQUEST: Duration = Duration::from_secs(30);
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Request error: {0}")]
     Request(#[from] reqwest::Error),
     #[error("JSON deserialization error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("TLS error: {0}")]
+    Tls(String),
     #[error("Server error: {0} - {1}")]
     ServerError(reqwest::StatusCode, String),
 }
 
-/// Check if the server is running on the configured address and port.
-pub async fn is_running() -> bool {
-    if let Ok(body) = get("info").await {
-        let name = env!("CARGO_PKG_NAME");
-        let version = env!("CARGO_PKG_VERSION");
-        let expected_body = format!("{{\"name\": \"{}\", \"version\": \"{}\"}}", name, version);
-        return body == expected_body;
+/// A client for the REST API.
+pub struct ApiClient {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl ApiClient {
+    /// Returns a client for the configured server.
+    pub fn new() -> Result<Self, ClientError> {
+        Ok(ApiClient::with_base_url(
+            client()?,
+            &CONFIG.server.to_string(),
+            CONFIG.auth.token.clone(),
+        ))
+    }
+
+    pub fn with_base_url(client: Client, base_url: &str, token: Option<String>) -> Self {
+        ApiClient {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    /// Returns the name and version of the server.
+    pub async fn info(&self) -> Result<Info, ClientError> {
+        self.send(self.request(Method::GET, "/info"), &[]).await
+    }
+
+    /// Returns the health status. An unhealthy status is returned with a 503 response, which is
+    /// not an error here.
+    pub async fn status(&self) -> Result<Status, ClientError> {
+        let request = self.request(Method::GET, "/status");
+        self.send(request, &[StatusCode::SERVICE_UNAVAILABLE]).await
     }
 
-    false
+    /// Changes the status. Only the fields that are set in the patch are changed.
+    pub async fn patch_status(&self, patch: &StatusPatch) -> Result<(), ClientError> {
+        debug!("PATCH /status with {:?}", patch);
+        let request = self
+            .request(Method::PATCH, "/status")
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(patch)?);
+        check(request.send().await?, &[]).await?;
+        Ok(())
+    }
+
+    /// Returns the running release, the deployment in progress and the past deployments.
+    pub async fn deployments(&self) -> Result<Deployments, ClientError> {
+        self.send(self.request(Method::GET, "/deployments"), &[])
+            .await
+    }
+
+    /// Waits until the status meets the condition, for at most the given timeout. Returns the
+    /// status, or `None` if the timeout expired first.
+    pub async fn wait(
+        &self,
+        condition: &Condition,
+        timeout: Duration,
+    ) -> Result<Option<Status>, ClientError> {
+        let mut query = vec![("timeout", format!("{}s", timeout.as_secs()))];
+        if let Some(state) = condition.state {
+            query.push(("state", state.to_string()));
+        }
+        if let Some(phase) = &condition.phase {
+            query.push(("phase", phase.to_string()));
+        }
+        let request = self.request(Method::GET, "/status/wait").query(&query);
+        let response = check(request.send().await?, &[StatusCode::REQUEST_TIMEOUT]).await?;
+        if response.status() == StatusCode::REQUEST_TIMEOUT {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&response.bytes().await?)?))
+    }
+
+    /// Returns a request to the given path, with the configured token, if any.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends a request and deserializes the response.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+        expected: &[StatusCode],
+    ) -> Result<T, ClientError> {
+        let response = check(request.send().await?, expected).await?;
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
+
+/// Returns an error for a response that is not successful, unless its status code is expected.
+async fn check(
+    response: reqwest::Response,
+    expected: &[StatusCode],
+) -> Result<reqwest::Response, ClientError> {
+    let status = response.status();
+    debug!("{} {}", status, response.url());
+    if status.is_success() || expected.contains(&status) {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    // Show the description of the problem rather than the JSON document.
+    let message = match serde_json::from_str::<Problem>(&body) {
+        Ok(problem) => problem.detail,
+        Err(_) => body,
+    };
+    Err(ClientError::ServerError(status, message))
+}
+
+/// Check if our server is running on the configured address and port.
+pub async fn is_running() -> bool {
+    match ApiClient::new() {
+        Ok(client) => client
+            .info()
+            .await
+            .is_ok_and(|info| info == Info::current()),
+        Err(_) => false,
+    }
 }
 
 /// Retrieve the health status from the server.
 pub async fn get_status() -> Result<Status, ClientError> {
-    let json = get("status").await?;
-    let status = serde_json::from_str(&json)?;
-    Ok(status)
+    ApiClient::new()?.status().await
 }
 
 /// Sets the health status on the server.
@@ -45,52 +176,101 @@ pub async fn set_health_state(
         "Setting health state: {} with message: {:?}",
         state, message
     );
-    let mut json = serde_json::json!({"health": state});
-    if let Some(message) = message {
-        json["message"] = serde_json::json!(message);
-    }
-    patch("status", &json.to_string()).await?;
-    Ok(())
+    let patch = StatusPatch {
+        health: Some(state),
+        message,
+        ..StatusPatch::default()
+    };
+    ApiClient::new()?.patch_status(&patch).await
 }
 
-/// Sets the deployment phase on the server.
-pub async fn set_deployment_phase(phase: DeploymentPhase) -> Result<(), ClientError> {
-    debug!("Setting deployment phase: {}", phase);
-    let json = serde_json::json!({"phase": phase});
-    patch("status", &json.to_string()).await?;
-    Ok(())
+/// Sets the deployment phase on the server, along with the metadata of the deployment.
+pub async fn set_deployment_phase(
+    phase: DeploymentPhase,
+    message: Option<String>,
+    deployment: DeploymentUpdate,
+) -> Result<(), ClientError> {
+    debug!(
+        "Setting deployment phase: {} with message: {:?} and deployment: {:?}",
+        phase, message, deployment
+    );
+    let patch = StatusPatch {
+        phase: Some(phase),
+        message,
+        deployment: (!deployment.is_empty()).then_some(deployment),
+        ..StatusPatch::default()
+    };
+    ApiClient::new()?.patch_status(&patch).await
 }
 
-/// Send a GET request to the server.
-async fn get(uri: &str) -> Result<String, ClientError> {
-    debug!("GET {}", uri);
-    let client = Client::new();
-    let response = client.get(get_url(uri)).send().await?;
-    let body = response.text().await?;
-    Ok(body)
+/// Marks a deployment as failed: the application is set to unhealthy with the given message, and
+/// the deployment is ended so the phase doesn't get stuck.
+pub async fn fail_deployment(message: String) -> Result<(), ClientError> {
+    debug!("Failing deployment with message: {}", message);
+    let patch = StatusPatch {
+        health: Some(HealthState::Unhealthy),
+        message: Some(message),
+        phase: Some(DeploymentPhase::Online),
+        deployment: None,
+    };
+    ApiClient::new()?.patch_status(&patch).await
 }
 
-/// Send a PATCH request to the server.
-async fn patch(uri: &str, body: &str) -> Result<(), ClientError> {
-    debug!("PATCH {} with body: {}", uri, body);
-    let client = Client::new();
-    let response = client
-        .patch(get_url(uri))
-        .header("Content-Type", "application/json")
-        .body(body.to_string())
-        .send()
-        .await?;
-    // If we get a 200 OK, return Ok(()), otherwise return an error.
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        let status = response.status();
-        let body = response.text().await?;
-        Err(ClientError::ServerError(status, body))
+/// Retrieve the running release and the deployment history from the server.
+pub async fn get_deployments() -> Result<Deployments, ClientError> {
+    ApiClient::new()?.deployments().await
+}
+
+/// Waits until the status on the server meets the condition. Returns the status, or `None` if the
+/// timeout expired first. Long waits are split into several requests, so idle connections are not
+/// cut by proxies along the way.
+pub async fn wait_for(
+    condition: &Condition,
+    timeout: Duration,
+) -> Result<Option<Status>, ClientError> {
+    debug!("Waiting for {} for {:?}", condition, timeout);
+    let client = ApiClient::new()?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let status = client.wait(condition, remaining.min(WAIT_REQUEST)).await?;
+        if status.is_some() || remaining <= WAIT_REQUEST {
+            return Ok(status);
+        }
     }
 }
 
-/// Generate the endpoint URL from the given URI.
-fn get_url(uri: &str) -> String {
-    format!("{}/{}", &CONFIG.server.to_string(), uri)
+/// Returns a client that connects to the Unix socket of the server if one is configured, or to the
+/// TCP port otherwise.
+fn client() -> Result<Client, ClientError> {
+    // The server recognizes the CLI by its user agent, to tell it apart in the audit log.
+    let mut builder = Client::builder().user_agent(USER_AGENT);
+    if let Some(socket) = &CONFIG.server.socket {
+        builder = builder.unix_socket(socket.as_str());
+    } else if CONFIG.server.is_tls() {
+        builder = with_tls(builder, &CONFIG.tls)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Configures the client to trust the CA of the server, and to present the client certificate if
+/// one is configured.
+pub fn with_tls(
+    mut builder: ClientBuilder,
+    config: &TlsConfig,
+) -> Result<ClientBuilder, ClientError> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|e| ClientError::Tls(format!("Failed to read {}: {}", path, e)))
+    };
+    if let Some(ca) = &config.ca {
+        for cert in Certificate::from_pem_bundle(&read(ca)?)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+        let mut pem = read(cert)?;
+        pem.extend(read(key)?);
+        builder = builder.identity(Identity::from_pem(&pem)?);
+    }
+    Ok(builder)
 }
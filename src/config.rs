@@ -1,50 +1,222 @@
 use crate::status::DeploymentPhase;
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::LazyLock;
 use std::{env, fmt};
 
 pub struct Config {
     pub server: ServerConfig,
+    pub auth: AuthConfig,
+    pub access: AccessConfig,
+    pub tls: TlsConfig,
+    pub audit: AuditConfig,
     pub checks: ChecksConfig,
 }
 
 impl Config {
+    /// Reads the configuration from the environment.
     pub fn new() -> Self {
-        let scheme = env::var("HEALTHMONITOR_SERVER_SCHEME").unwrap_or_else(|_| "http".to_string());
+        Config::from_vars(&env::vars().collect())
+    }
+
+    /// Reads the configuration from the given variables, e.g. the environment merged with a
+    /// reloaded .env file.
+    pub fn from_vars(vars: &HashMap<String, String>) -> Self {
+        let var = |name: &str| vars.get(name).cloned().ok_or(env::VarError::NotPresent);
+        let scheme = var("HEALTHMONITOR_SERVER_SCHEME").unwrap_or_else(|_| "http".to_string());
         let address =
-            env::var("HEALTHMONITOR_SERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_string());
-        let port = env::var("HEALTHMONITOR_SERVER_PORT")
+            var("HEALTHMONITOR_SERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1".to_string());
+        // An empty port disables the TCP listener, e.g. when only the Unix socket is used.
+        let port = match var("HEALTHMONITOR_SERVER_PORT") {
+            Ok(p) if p.trim().is_empty() => None,
+            Ok(p) => Some(p.parse().unwrap_or(8080)),
+            Err(_) => Some(8080),
+        };
+        let admin_address = var("HEALTHMONITOR_ADMIN_ADDRESS")
             .ok()
-            .and_then(|p| p.parse().ok())
-            .unwrap_or(8080);
+            .filter(|a| !a.trim().is_empty())
+            .unwrap_or_else(|| address.clone());
+        let admin_port = var("HEALTHMONITOR_ADMIN_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let socket = var("HEALTHMONITOR_SERVER_SOCKET")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let socket_mode = var("HEALTHMONITOR_SERVER_SOCKET_MODE")
+            .ok()
+            .and_then(|m| u32::from_str_radix(m.trim(), 8).ok())
+            .unwrap_or(0o660);
+        let socket_owner = var("HEALTHMONITOR_SERVER_SOCKET_OWNER")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
         let phase = DeploymentPhase::try_from(
-            env::var("HEALTHMONITOR_SERVER_PHASE")
+            var("HEALTHMONITOR_SERVER_PHASE")
                 .unwrap_or_else(|_| "online".to_string())
                 .as_str(),
         )
         .unwrap_or(DeploymentPhase::Online);
+        let grpc_port = var("HEALTHMONITOR_GRPC_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let agent_port = var("HEALTHMONITOR_AGENT_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let zabbix_port = var("HEALTHMONITOR_ZABBIX_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let ready_while_deploying = var("HEALTHMONITOR_READY_WHILE_DEPLOYING")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(true);
+        let drain_period = var("HEALTHMONITOR_DRAIN_PERIOD")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(30);
+        let drain_shutdown = var("HEALTHMONITOR_DRAIN_SHUTDOWN")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(false);
+        let shutdown_grace_period = var("HEALTHMONITOR_SHUTDOWN_GRACE_PERIOD")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(5);
+        let deploy_timeout = var("HEALTHMONITOR_DEPLOY_TIMEOUT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let deploy_history = var("HEALTHMONITOR_DEPLOY_HISTORY")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(10);
+        let deploy_timeout_policy = DeployTimeoutPolicy::try_from(
+            var("HEALTHMONITOR_DEPLOY_TIMEOUT_POLICY")
+                .unwrap_or_else(|_| "unhealthy".to_string())
+                .as_str(),
+        )
+        .unwrap_or(DeployTimeoutPolicy::Unhealthy);
+
+        let auth_tokens = var("HEALTHMONITOR_AUTH_TOKENS")
+            .unwrap_or_else(|_| "".to_string())
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().to_string())
+            .collect();
+        let auth_tokens_file = var("HEALTHMONITOR_AUTH_TOKENS_FILE")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        // The token the CLI sends, read from a file so it doesn't show up in the environment of
+        // every process.
+        let token = var("HEALTHMONITOR_TOKEN_FILE")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .or_else(|| var("HEALTHMONITOR_TOKEN").ok())
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+
+        let networks = |name: &str| -> Vec<String> {
+            var(name)
+                .unwrap_or_else(|_| "".to_string())
+                .split(',')
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| s.trim().to_string())
+                .collect()
+        };
+        let allow_read = networks("HEALTHMONITOR_ALLOW_READ");
+        let allow_write = networks("HEALTHMONITOR_ALLOW_WRITE");
+        let trusted_proxies = networks("HEALTHMONITOR_TRUSTED_PROXIES");
+        let rate_limit = var("HEALTHMONITOR_RATE_LIMIT")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let rate_limit_burst = var("HEALTHMONITOR_RATE_LIMIT_BURST")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(10);
+
+        let tls_file = |name: &str| var(name).ok().filter(|s| !s.trim().is_empty());
+        let tls_cert = tls_file("HEALTHMONITOR_TLS_CERT");
+        let tls_key = tls_file("HEALTHMONITOR_TLS_KEY");
+        let tls_ca = tls_file("HEALTHMONITOR_TLS_CA");
+        let tls_client_auth = var("HEALTHMONITOR_TLS_CLIENT_AUTH")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(false);
+        let tls_client_cert = tls_file("HEALTHMONITOR_TLS_CLIENT_CERT");
+        let tls_client_key = tls_file("HEALTHMONITOR_TLS_CLIENT_KEY");
+
+        let audit_file = var("HEALTHMONITOR_AUDIT_FILE")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let audit_max_size = var("HEALTHMONITOR_AUDIT_MAX_SIZE")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(10 * 1024 * 1024);
+        let audit_files = var("HEALTHMONITOR_AUDIT_FILES")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(5);
+
+        let file_check_interval = var("HEALTHMONITOR_FILECHECK_INTERVAL")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(30);
+        let file_check_files = var("HEALTHMONITOR_FILECHECK_FILES")
+            .unwrap_or_else(|_| "".to_string())
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().to_string())
+            .collect();
+
+        let url_check_interval = var("HEALTHMONITOR_URLCHECK_INTERVAL")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(30);
+        let url_check_urls = var("HEALTHMONITOR_URLCHECK_URLS")
+            .unwrap_or_else(|_| "".to_string())
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().to_string())
+            .collect();
+        let url_check_timeout = var("HEALTHMONITOR_URLCHECK_TIMEOUT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(10);
 
-        let file_check_interval = env::var("HEALTHMONITOR_FILECHECK_INTERVAL")
+        let fpm_check_interval = var("HEALTHMONITOR_FPMCHECK_INTERVAL")
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(30);
-        let file_check_files = env::var("HEALTHMONITOR_FILECHECK_FILES")
+        let fpm_check_addresses = var("HEALTHMONITOR_FPMCHECK_ADDRESSES")
             .unwrap_or_else(|_| "".to_string())
             .split(',')
             .filter(|s| !s.trim().is_empty())
             .map(|s| s.trim().to_string())
             .collect();
+        let fpm_check_status_path =
+            var("HEALTHMONITOR_FPMCHECK_STATUS_PATH").unwrap_or_else(|_| "/status".to_string());
+        let fpm_check_timeout = var("HEALTHMONITOR_FPMCHECK_TIMEOUT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(10);
+        let fpm_check_max_listen_queue = var("HEALTHMONITOR_FPMCHECK_MAX_LISTEN_QUEUE")
+            .ok()
+            .and_then(|p| p.parse().ok());
+        let fpm_check_max_saturation = var("HEALTHMONITOR_FPMCHECK_MAX_SATURATION")
+            .ok()
+            .and_then(|p| p.parse().ok());
 
-        let url_check_interval = env::var("HEALTHMONITOR_URLCHECK_INTERVAL")
+        let grpc_check_interval = var("HEALTHMONITOR_GRPCCHECK_INTERVAL")
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(30);
-        let url_check_urls = env::var("HEALTHMONITOR_URLCHECK_URLS")
+        let grpc_check_targets = var("HEALTHMONITOR_GRPCCHECK_TARGETS")
             .unwrap_or_else(|_| "".to_string())
             .split(',')
             .filter(|s| !s.trim().is_empty())
             .map(|s| s.trim().to_string())
             .collect();
-        let url_check_timeout = env::var("HEALTHMONITOR_URLCHECK_TIMEOUT")
+        let grpc_check_service =
+            var("HEALTHMONITOR_GRPCCHECK_SERVICE").unwrap_or_else(|_| "".to_string());
+        let grpc_check_timeout = var("HEALTHMONITOR_GRPCCHECK_TIMEOUT")
             .ok()
             .and_then(|p| p.parse().ok())
             .unwrap_or(10);
@@ -54,7 +226,47 @@ impl Config {
                 scheme,
                 address,
                 port,
+                admin_address,
+                admin_port,
+                socket,
+                socket_mode,
+                socket_owner,
                 phase,
+                grpc_port,
+                agent_port,
+                zabbix_port,
+                ready_while_deploying,
+                drain_period,
+                drain_shutdown,
+                shutdown_grace_period,
+                deploy_timeout,
+                deploy_timeout_policy,
+                deploy_history,
+            },
+            auth: AuthConfig {
+                tokens: auth_tokens,
+                tokens_file: auth_tokens_file,
+                token,
+            },
+            access: AccessConfig {
+                allow_read,
+                allow_write,
+                trusted_proxies,
+                rate_limit,
+                rate_limit_burst,
+            },
+            tls: TlsConfig {
+                cert: tls_cert,
+                key: tls_key,
+                ca: tls_ca,
+                client_auth: tls_client_auth,
+                client_cert: tls_client_cert,
+                client_key: tls_client_key,
+            },
+            audit: AuditConfig {
+                file: audit_file,
+                max_size: audit_max_size,
+                files: audit_files,
             },
             checks: ChecksConfig {
                 file_check: FileCheckConfig {
@@ -66,6 +278,20 @@ impl Config {
                     urls: url_check_urls,
                     timeout: url_check_timeout,
                 },
+                fpm_check: FpmCheckConfig {
+                    interval: fpm_check_interval,
+                    addresses: fpm_check_addresses,
+                    status_path: fpm_check_status_path,
+                    timeout: fpm_check_timeout,
+                    max_listen_queue: fpm_check_max_listen_queue,
+                    max_saturation: fpm_check_max_saturation,
+                },
+                grpc_check: GrpcCheckConfig {
+                    interval: grpc_check_interval,
+                    targets: grpc_check_targets,
+                    service: grpc_check_service,
+                    timeout: grpc_check_timeout,
+                },
             },
         }
     }
@@ -75,6 +301,10 @@ impl fmt::Debug for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Config")
             .field("server", &self.server)
+            .field("auth", &self.auth)
+            .field("access", &self.access)
+            .field("tls", &self.tls)
+            .field("audit", &self.audit)
             .field("checks", &self.checks)
             .finish()
     }
@@ -83,8 +313,29 @@ impl fmt::Debug for Config {
 pub struct ServerConfig {
     pub scheme: String,
     pub address: String,
-    pub port: u16,
+    pub port: Option<u16>,
+    /// The address and port of the admin listener, which serves the endpoints that change the
+    /// status. When an admin listener or a Unix socket is configured, the server port is
+    /// read-only.
+    pub admin_address: String,
+    pub admin_port: Option<u16>,
+    /// The path of the Unix socket to listen on. Like the admin port, it serves all endpoints.
+    pub socket: Option<String>,
+    /// The file mode of the Unix socket.
+    pub socket_mode: u32,
+    /// The owner of the Unix socket, as `user`, `user:group` or `:group`.
+    pub socket_owner: Option<String>,
     pub phase: DeploymentPhase,
+    pub grpc_port: Option<u16>,
+    pub agent_port: Option<u16>,
+    pub zabbix_port: Option<u16>,
+    pub ready_while_deploying: bool,
+    pub drain_period: usize,
+    pub drain_shutdown: bool,
+    pub shutdown_grace_period: usize,
+    pub deploy_timeout: Option<usize>,
+    pub deploy_timeout_policy: DeployTimeoutPolicy,
+    pub deploy_history: usize,
 }
 
 impl fmt::Debug for ServerConfig {
@@ -93,20 +344,137 @@ impl fmt::Debug for ServerConfig {
             .field("scheme", &self.scheme)
             .field("address", &self.address)
             .field("port", &self.port)
+            .field("admin_address", &self.admin_address)
+            .field("admin_port", &self.admin_port)
+            .field("socket", &self.socket)
+            .field("socket_mode", &format_args!("{:o}", self.socket_mode))
+            .field("socket_owner", &self.socket_owner)
+            .field("grpc_port", &self.grpc_port)
+            .field("agent_port", &self.agent_port)
+            .field("zabbix_port", &self.zabbix_port)
+            .field("ready_while_deploying", &self.ready_while_deploying)
+            .field("drain_period", &self.drain_period)
+            .field("drain_shutdown", &self.drain_shutdown)
+            .field("shutdown_grace_period", &self.shutdown_grace_period)
+            .field("deploy_timeout", &self.deploy_timeout)
+            .field("deploy_timeout_policy", &self.deploy_timeout_policy)
+            .field("deploy_history", &self.deploy_history)
             .finish()
     }
 }
 
 impl fmt::Display for ServerConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}://{}:{}", self.scheme, self.address, self.port)
+        // The URL of the admin listener if there is one, since the CLI changes the status.
+        match (&self.socket, self.admin_port, self.port) {
+            // Requests over the Unix socket still need a host in the URL. The socket doesn't use
+            // TLS, its file permissions protect it.
+            (Some(_), _, _) => write!(f, "http://localhost"),
+            (None, None, None) => write!(f, "{}://localhost", self.scheme),
+            (None, Some(port), _) => write!(f, "{}://{}:{}", self.scheme, self.admin_address, port),
+            (None, None, Some(port)) => write!(f, "{}://{}:{}", self.scheme, self.address, port),
+        }
     }
 }
 
+impl ServerConfig {
+    /// Whether the TCP listeners speak TLS.
+    pub fn is_tls(&self) -> bool {
+        self.scheme == "https"
+    }
+
+    /// Whether the endpoints that change the status are served on a listener of their own.
+    pub fn has_admin_listener(&self) -> bool {
+        self.admin_port.is_some() || self.socket.is_some()
+    }
+}
+
+/// What to do when a deployment takes longer than the configured deploy timeout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeployTimeoutPolicy {
+    /// Assume the deployment failed and mark the application as unhealthy.
+    Unhealthy,
+    /// Assume the deployment script died after a successful deployment and put the application
+    /// online, so the health checks take over.
+    Online,
+}
+
+impl TryFrom<&str> for DeployTimeoutPolicy {
+    type Error = &'static str;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "unhealthy" => Ok(DeployTimeoutPolicy::Unhealthy),
+            "online" => Ok(DeployTimeoutPolicy::Online),
+            _ => Err("Invalid deploy timeout policy"),
+        }
+    }
+}
+
+pub struct AuthConfig {
+    /// The tokens that give access to the API, in the form `id:role:secret`.
+    pub tokens: Vec<String>,
+    /// A file with more tokens, one per line.
+    pub tokens_file: Option<String>,
+    /// The token the CLI sends to the server.
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't leak the secrets in the logs.
+        f.debug_struct("AuthConfig")
+            .field("tokens", &self.tokens.len())
+            .field("tokens_file", &self.tokens_file)
+            .field("token", &self.token.as_ref().map(|_| "********"))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct AccessConfig {
+    /// The networks that may read the status. Empty allows everyone.
+    pub allow_read: Vec<String>,
+    /// The networks that may change the status. Empty allows everyone.
+    pub allow_write: Vec<String>,
+    /// The proxies whose `X-Forwarded-For` header is trusted.
+    pub trusted_proxies: Vec<String>,
+    /// The number of requests per second a client can make on average.
+    pub rate_limit: Option<f64>,
+    /// The number of requests a client can make at once.
+    pub rate_limit_burst: usize,
+}
+
+#[derive(Debug)]
+pub struct TlsConfig {
+    /// The certificate chain and private key the server uses when the scheme is `https`.
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// The CA that signed the server certificate, and the client certificates if they are
+    /// verified. The CLI trusts it too.
+    pub ca: Option<String>,
+    /// Whether clients need a certificate signed by the CA.
+    pub client_auth: bool,
+    /// The certificate and private key the CLI presents to the server.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AuditConfig {
+    /// The JSON Lines file the audit records are appended to.
+    pub file: Option<String>,
+    /// The size in bytes at which the file is rotated.
+    pub max_size: u64,
+    /// The number of rotated files to keep.
+    pub files: usize,
+}
+
 #[derive(Debug)]
 pub struct ChecksConfig {
     pub file_check: FileCheckConfig,
     pub url_check: UrlCheckConfig,
+    pub fpm_check: FpmCheckConfig,
+    pub grpc_check: GrpcCheckConfig,
 }
 
 #[derive(Debug)]
@@ -122,4 +490,84 @@ pub struct UrlCheckConfig {
     pub timeout: usize,
 }
 
+#[derive(Debug)]
+pub struct FpmCheckConfig {
+    pub interval: usize,
+    pub addresses: Vec<String>,
+    pub status_path: String,
+    pub timeout: usize,
+    pub max_listen_queue: Option<usize>,
+    pub max_saturation: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct GrpcCheckConfig {
+    pub interval: usize,
+    pub targets: Vec<String>,
+    pub service: String,
+    pub timeout: usize,
+}
+
 pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::new);
+
+/// Returns the variables in a .env file: `KEY=value` lines, optionally quoted and prefixed with
+/// `export`. Empty lines and comments are skipped.
+pub fn read_env_file(path: &Path) -> std::io::Result<HashMap<String, String>> {
+    Ok(parse_env_file(&std::fs::read_to_string(path)?))
+}
+
+fn parse_env_file(contents: &str) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) if value.len() > 1 && value.ends_with(quote) => {
+                &value[1..value.len() - 1]
+            }
+            // Unquoted values can be followed by a comment.
+            _ => value.split(" #").next().unwrap_or_default().trim_end(),
+        };
+        vars.insert(key.trim().to_string(), value.to_string());
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let vars = parse_env_file(
+            "# Comment\n\nA=1\nexport B = two words # comment\nC=\"quoted # not a comment\"\nD='single'\nE=\ninvalid\n",
+        );
+        assert_eq!(vars["A"], "1");
+        assert_eq!(vars["B"], "two words");
+        assert_eq!(vars["C"], "quoted # not a comment");
+        assert_eq!(vars["D"], "single");
+        assert_eq!(vars["E"], "");
+        assert_eq!(vars.len(), 5);
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let vars = HashMap::from([
+            ("HEALTHMONITOR_SERVER_PORT".to_string(), "9090".to_string()),
+            (
+                "HEALTHMONITOR_FILECHECK_FILES".to_string(),
+                "/a, /b".to_string(),
+            ),
+        ]);
+        let config = Config::from_vars(&vars);
+        assert_eq!(config.server.port, Some(9090));
+        assert_eq!(config.checks.file_check.files, ["/a", "/b"]);
+    }
+}
@@ -0,0 +1,33 @@
+/// This module serves a small HTML dashboard with the status, for humans. The page is
+/// self-contained: it has no external assets, and follows the status through the server-sent
+/// events of `/status/events`, so it updates live.
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Only allows the inline script and style of the page, and requests to the server itself.
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'";
+
+pub async fn dashboard() -> Response {
+    (
+        [(header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY)],
+        Html(PAGE),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_contained() {
+        // The page works without internet access, e.g. through an SSH tunnel.
+        assert!(!PAGE.contains("http://"));
+        assert!(!PAGE.contains("https://"));
+        assert!(!PAGE.contains("src="));
+        assert!(PAGE.contains("status/events"));
+    }
+}
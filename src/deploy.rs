@@ -0,0 +1,210 @@
+/// This module runs a deployment command on behalf of a deploy script. The application is put in
+/// the "deploying" phase while the command runs, and put online when it succeeds. When it fails or
+/// is interrupted the application is marked as unhealthy, so a failed deployment is never
+/// reported as healthy.
+use crate::client;
+use crate::process::{drain, exit_code, forward, OutputTail, DRAIN_TIMEOUT};
+use crate::status::{DeploymentPhase, DeploymentUpdate};
+
+use log::{debug, warn};
+use nix::sys::signal::Signal;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// The number of lines of error output to include in the status message of a failed deployment.
+const STDERR_LINES: usize = 5;
+
+/// The result of a deployment command.
+#[derive(Debug, PartialEq)]
+pub struct CommandResult {
+    /// The exit code of the command, or 128 + the signal number if it was killed by a signal.
+    pub code: i32,
+    /// The last lines the command wrote to stderr.
+    pub stderr: Vec<String>,
+    /// The signal that interrupted the deployment, if any.
+    pub interrupted: Option<Signal>,
+}
+
+impl CommandResult {
+    /// Returns the status message for a failed deployment.
+    fn failure_message(&self, command: &[String]) -> String {
+        let mut message = match self.interrupted {
+            Some(signal) => format!(
+                "The deployment command `{}` was interrupted by {}.",
+                command.join(" "),
+                signal
+            ),
+            None => format!(
+                "The deployment command `{}` failed with exit code {}.",
+                command.join(" "),
+                self.code
+            ),
+        };
+        if !self.stderr.is_empty() {
+            message.push('\n');
+            message.push_str(&self.stderr.join("\n"));
+        }
+        message
+    }
+}
+
+/// Runs the deployment command and reports the outcome to the server. Returns the exit code to
+/// exit with.
+pub async fn deploy(command: &[String], release: DeploymentUpdate) -> i32 {
+    if let Err(e) = client::set_deployment_phase(DeploymentPhase::Deploying, None, release).await {
+        eprintln!("Failed to start the deployment: {}", e);
+        return 1;
+    }
+
+    let result = run_command(command).await;
+    debug!("Deployment command finished: {:?}", result);
+
+    let reported = if result.code == 0 && result.interrupted.is_none() {
+        client::set_deployment_phase(DeploymentPhase::Online, None, DeploymentUpdate::default())
+            .await
+    } else {
+        client::fail_deployment(result.failure_message(command)).await
+    };
+    if let Err(e) = reported {
+        eprintln!("Failed to report the outcome of the deployment: {}", e);
+        return 1;
+    }
+
+    result.code
+}
+
+/// Runs the command, streaming its output, and returns the result. SIGTERM and SIGHUP are
+/// forwarded to the command. SIGINT is not, since pressing Ctrl+C already sends it to the whole
+/// process group. If a second signal arrives the command is killed.
+pub async fn run_command(command: &[String]) -> CommandResult {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    let mut sighup = signal(SignalKind::hangup()).unwrap();
+    let mut sigint = signal(SignalKind::interrupt()).unwrap();
+
+    let mut child = match Command::new(&command[0])
+        .args(&command[1..])
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            return CommandResult {
+                code: 127,
+                stderr: vec![format!("Failed to start {}: {}", command[0], e)],
+                interrupted: None,
+            }
+        }
+    };
+    let stderr = OutputTail::new(STDERR_LINES);
+    let capture = stderr.capture(child.stderr.take().unwrap(), tokio::io::stderr());
+
+    let mut interrupted = None;
+    let status = loop {
+        let signal = tokio::select! {
+            status = child.wait() => break status,
+            _ = sigterm.recv() => Signal::SIGTERM,
+            _ = sighup.recv() => Signal::SIGHUP,
+            _ = sigint.recv() => Signal::SIGINT,
+        };
+        warn!("Received {} while deploying.", signal);
+        if interrupted.replace(signal).is_some() {
+            let _ = child.start_kill();
+        } else if signal != Signal::SIGINT {
+            forward(&child, signal);
+        }
+    };
+
+    let code = match status {
+        Ok(status) => exit_code(status),
+        Err(e) => {
+            warn!("Failed to wait for the deployment command: {}", e);
+            1
+        }
+    };
+    drain(vec![capture], DRAIN_TIMEOUT).await;
+    CommandResult {
+        code,
+        stderr: stderr.lines(),
+        interrupted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn shell(script: &str) -> Vec<String> {
+        vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_successful_command() {
+        let result = run_command(&shell("echo Deploying; echo Warning >&2")).await;
+        assert_eq!(
+            result,
+            CommandResult {
+                code: 0,
+                stderr: vec!["Warning".to_string()],
+                interrupted: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_command() {
+        let command = shell("for i in 1 2 3 4 5 6 7; do echo Error $i >&2; done; exit 3");
+        let result = run_command(&command).await;
+        assert_eq!(result.code, 3);
+        assert_eq!(
+            result.stderr,
+            vec!["Error 3", "Error 4", "Error 5", "Error 6", "Error 7"]
+        );
+        assert_eq!(
+            result.failure_message(&["drush".to_string(), "deploy".to_string()]),
+            "The deployment command `drush deploy` failed with exit code 3.\nError 3\nError 4\nError 5\nError 6\nError 7"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binary_output() {
+        // A progress line in Latin-1 doesn't stop the output from being read, which would kill
+        // the command with SIGPIPE.
+        let command = shell("printf 'Copying caf\\351.txt\\n' >&2; sleep 0.1; echo Done >&2");
+        let result = run_command(&command).await;
+        assert_eq!(result.code, 0);
+        assert_eq!(result.stderr, vec!["Copying caf\u{FFFD}.txt", "Done"]);
+    }
+
+    #[tokio::test]
+    async fn test_command_starts_daemon() {
+        // The daemon keeps stderr open after the command exited.
+        let command = shell("echo Starting >&2; sleep 30 &");
+        let result = tokio::time::timeout(Duration::from_secs(5), run_command(&command))
+            .await
+            .expect("The command should not wait for the daemon.");
+        assert_eq!(result.code, 0);
+        assert_eq!(result.stderr, vec!["Starting"]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_command() {
+        let result = run_command(&["healthmonitor-missing-command".to_string()]).await;
+        assert_eq!(result.code, 127);
+        assert_eq!(result.stderr.len(), 1);
+    }
+
+    #[test]
+    fn test_interrupted_message() {
+        let result = CommandResult {
+            code: 143,
+            stderr: vec![],
+            interrupted: Some(Signal::SIGTERM),
+        };
+        assert_eq!(
+            result.failure_message(&["drush".to_string(), "deploy".to_string()]),
+            "The deployment command `drush deploy` was interrupted by SIGTERM."
+        );
+    }
+}
@@ -0,0 +1,246 @@
+/// This module follows the deployment phase of the application and acts on phases that are
+/// limited in time: the drain period of a draining instance, and the maximum duration of a
+/// deployment.
+use crate::audit::Source;
+use crate::config::{Config, DeployTimeoutPolicy};
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub struct DeploymentMonitor {
+    drain_period: Duration,
+    drain_shutdown: bool,
+    deploy_timeout: Option<Duration>,
+    deploy_timeout_policy: DeployTimeoutPolicy,
+}
+
+impl DeploymentMonitor {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            drain_period: Duration::from_secs(config.server.drain_period as u64),
+            drain_shutdown: config.server.drain_shutdown,
+            deploy_timeout: config
+                .server
+                .deploy_timeout
+                .map(|timeout| Duration::from_secs(timeout as u64)),
+            deploy_timeout_policy: config.server.deploy_timeout_policy,
+        }
+    }
+
+    /// Follows the deployment phase until the drain period of a draining instance has ended and
+    /// the health monitor is configured to shut down afterwards.
+    pub async fn run(&self, status: Arc<Mutex<Status>>) {
+        let mut changes = status.lock().await.subscribe();
+        // The start of the phase for which the time limit has already been handled.
+        let mut handled: Option<DateTime<Utc>> = None;
+
+        loop {
+            let limit = {
+                let status = status.lock().await;
+                match self.time_limit(&status.phase) {
+                    Some(period) if handled != Some(status.phase_since) => {
+                        Some((status.phase.clone(), status.phase_since, period))
+                    }
+                    _ => None,
+                }
+            };
+
+            let Some((phase, started, period)) = limit else {
+                changes.changed().await.ok();
+                continue;
+            };
+
+            let remaining = remaining(started, period);
+            debug!("The {} phase ends in {:?}", phase, remaining);
+            tokio::select! {
+                _ = tokio::time::sleep(remaining) => {
+                    handled = Some(started);
+                    match phase {
+                        DeploymentPhase::Draining => {
+                            info!("The drain period has ended.");
+                            if self.drain_shutdown {
+                                return;
+                            }
+                        }
+                        _ => self.deploy_timed_out(&status, started, period).await,
+                    }
+                }
+                _ = changes.changed() => {}
+            }
+        }
+    }
+
+    /// Returns how long the application is allowed to stay in the given phase.
+    fn time_limit(&self, phase: &DeploymentPhase) -> Option<Duration> {
+        match phase {
+            DeploymentPhase::Draining => Some(self.drain_period),
+            DeploymentPhase::Deploying => self.deploy_timeout,
+            DeploymentPhase::Online | DeploymentPhase::Maintenance => None,
+        }
+    }
+
+    /// Applies the deploy timeout policy to a deployment that took too long.
+    async fn deploy_timed_out(
+        &self,
+        status: &Arc<Mutex<Status>>,
+        started: DateTime<Utc>,
+        timeout: Duration,
+    ) {
+        let mut status = status.lock().await;
+        // The deployment might have completed while we were waiting for the lock.
+        if status.phase != DeploymentPhase::Deploying || status.phase_since != started {
+            return;
+        }
+
+        let message = format!(
+            "The deployment did not complete within {} seconds.",
+            timeout.as_secs_f64()
+        );
+        let before = status.snapshot();
+        match self.deploy_timeout_policy {
+            DeployTimeoutPolicy::Unhealthy => {
+                warn!("{} Marking the application as unhealthy.", message);
+                status.set_state(HealthState::Unhealthy);
+                status.add_message(message);
+            }
+            DeployTimeoutPolicy::Online => {
+                warn!("{} Putting the application online.", message);
+                status.add_message(message);
+                status.set_phase(DeploymentPhase::Online);
+            }
+        }
+        let source = Source::System {
+            name: "deploy timeout".to_string(),
+        };
+        status.audit(before, source, None);
+    }
+}
+
+/// Returns the time that is left of a period that started at the given time.
+fn remaining(start: DateTime<Utc>, period: Duration) -> Duration {
+    let elapsed = (Utc::now() - start).to_std().unwrap_or_default();
+    period.saturating_sub(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    fn monitor(drain_period: u64, drain_shutdown: bool) -> DeploymentMonitor {
+        DeploymentMonitor {
+            drain_period: Duration::from_millis(drain_period),
+            drain_shutdown,
+            deploy_timeout: None,
+            deploy_timeout_policy: DeployTimeoutPolicy::Unhealthy,
+        }
+    }
+
+    fn deploy_monitor(deploy_timeout: u64, policy: DeployTimeoutPolicy) -> DeploymentMonitor {
+        DeploymentMonitor {
+            deploy_timeout: Some(Duration::from_millis(deploy_timeout)),
+            deploy_timeout_policy: policy,
+            ..monitor(100, true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_after_drain_period() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = monitor(200, true);
+        let run = monitor.run(status.clone());
+        tokio::pin!(run);
+
+        // Nothing happens while the application is online.
+        assert!(timeout(Duration::from_millis(300), &mut run).await.is_err());
+
+        status.lock().await.set_phase(DeploymentPhase::Draining);
+        assert!(timeout(Duration::from_millis(100), &mut run).await.is_err());
+        assert!(timeout(Duration::from_millis(300), &mut run).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_drain() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = monitor(200, true);
+        let run = monitor.run(status.clone());
+        tokio::pin!(run);
+
+        status.lock().await.set_phase(DeploymentPhase::Draining);
+        assert!(timeout(Duration::from_millis(100), &mut run).await.is_err());
+        status.lock().await.set_phase(DeploymentPhase::Online);
+        assert!(timeout(Duration::from_millis(300), &mut run).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_shutdown_after_drain_period() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = monitor(100, false);
+        status.lock().await.set_phase(DeploymentPhase::Draining);
+        assert!(
+            timeout(Duration::from_millis(300), monitor.run(status.clone()))
+                .await
+                .is_err()
+        );
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Draining);
+    }
+
+    #[tokio::test]
+    async fn test_deploy_timeout_unhealthy() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = deploy_monitor(200, DeployTimeoutPolicy::Unhealthy);
+        let run = monitor.run(status.clone());
+        tokio::pin!(run);
+
+        status.lock().await.set_phase(DeploymentPhase::Deploying);
+        assert!(timeout(Duration::from_millis(100), &mut run).await.is_err());
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        assert!(timeout(Duration::from_millis(300), &mut run).await.is_err());
+        let status = status.lock().await;
+        assert_eq!(status.state, HealthState::Unhealthy);
+        assert_eq!(status.phase, DeploymentPhase::Deploying);
+        assert_eq!(
+            status.messages,
+            vec!["The deployment did not complete within 0.2 seconds.".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deploy_timeout_online() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = deploy_monitor(100, DeployTimeoutPolicy::Online);
+        status.lock().await.set_phase(DeploymentPhase::Deploying);
+
+        assert!(
+            timeout(Duration::from_millis(300), monitor.run(status.clone()))
+                .await
+                .is_err()
+        );
+        let status = status.lock().await;
+        assert_eq!(status.state, HealthState::Healthy);
+        assert_eq!(status.phase, DeploymentPhase::Online);
+        assert_eq!(status.deployment, None);
+    }
+
+    #[tokio::test]
+    async fn test_completed_deployment() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let monitor = deploy_monitor(200, DeployTimeoutPolicy::Unhealthy);
+        let run = monitor.run(status.clone());
+        tokio::pin!(run);
+
+        status.lock().await.set_phase(DeploymentPhase::Deploying);
+        assert!(timeout(Duration::from_millis(100), &mut run).await.is_err());
+        status.lock().await.set_phase(DeploymentPhase::Online);
+        assert!(timeout(Duration::from_millis(300), &mut run).await.is_err());
+
+        let status = status.lock().await;
+        assert_eq!(status.state, HealthState::Healthy);
+        assert!(status.messages.is_empty());
+    }
+}
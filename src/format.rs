@@ -0,0 +1,216 @@
+/// This module renders the status in the formats that `GET /status` supports besides JSON: the
+/// one-line plain text form for simple load balancers and scripts that grep the response, and the
+/// output of a Nagios plugin, with performance data, for legacy monitoring systems. The format is
+/// chosen with the `format` query parameter, or else with the `Accept` header.
+use crate::problem::Problem;
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use axum::http::{header, HeaderMap, StatusCode};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StatusQuery {
+    /// The format of the response: json, text, nagios or html. Overrides the Accept header.
+    pub format: Option<String>,
+    /// Whether to include the result of every check in the text and Nagios formats.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Text,
+    Nagios,
+    Html,
+}
+
+impl TryFrom<&str> for Format {
+    type Error = &'static str;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "text" => Ok(Format::Text),
+            "nagios" => Ok(Format::Nagios),
+            "html" => Ok(Format::Html),
+            _ => Err("Invalid format, use json, text, nagios or html."),
+        }
+    }
+}
+
+impl Format {
+    /// Returns the format of the query, or else the format the client prefers according to its
+    /// Accept header. JSON is the default.
+    pub fn negotiate(query: &StatusQuery, headers: &HeaderMap) -> Result<Format, Problem> {
+        if let Some(format) = &query.format {
+            return Format::try_from(format.as_str())
+                .map_err(|e| Problem::field(StatusCode::BAD_REQUEST, "format", e));
+        }
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+        Ok(preferred(accept))
+    }
+}
+
+/// Returns the format of the media type with the highest quality in an Accept header. The first
+/// one wins if several have the same quality.
+fn preferred(accept: &str) -> Format {
+    let mut best = (Format::Json, 0.0);
+    for range in accept.split(',') {
+        let mut parameters = range.split(';').map(str::trim);
+        let format = match parameters.next().unwrap_or_default() {
+            "application/json" => Format::Json,
+            "text/plain" => Format::Text,
+            "text/html" => Format::Html,
+            _ => continue,
+        };
+        let quality = parameters
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .and_then(|quality| quality.parse().ok())
+            .unwrap_or(1.0);
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+    best.0
+}
+
+/// Returns the status in the one-line form of its `Display` implementation, preceded by the result
+/// of every check if verbose.
+pub fn text(status: &Status, verbose: bool) -> String {
+    let mut body = String::new();
+    if verbose {
+        for (name, result) in &status.checks {
+            match &result.error {
+                None => body.push_str(&format!("[+]{} ok\n", name)),
+                Some(error) => body.push_str(&format!("[-]{} failed: {}\n", name, error)),
+            }
+        }
+    }
+    body.push_str(&format!("{}\n", status));
+    body
+}
+
+/// Returns the status as the output of a Nagios plugin: a line with the service state and the
+/// performance data, followed by the result of every check if verbose.
+pub fn nagios(status: &Status, verbose: bool) -> String {
+    let label = if status.state == HealthState::Unhealthy {
+        "CRITICAL"
+    } else if status.status_code() != StatusCode::OK {
+        // Draining or in maintenance on purpose.
+        "WARNING"
+    } else {
+        "OK"
+    };
+    let mut line = format!("{} - {}, {}", label, status.state, status.phase);
+    if !status.messages.is_empty() {
+        line.push_str(": ");
+        line.push_str(&status.messages.join(", "));
+    }
+
+    let failed = status
+        .checks
+        .values()
+        .filter(|result| !result.passed)
+        .count();
+    let mut perfdata = vec![
+        format!("checks={};;;0", status.checks.len()),
+        format!("failed_checks={};;;0", failed),
+        format!("messages={};;;0", status.messages.len()),
+    ];
+    if let (DeploymentPhase::Deploying, Some(deployment)) = (&status.phase, &status.deployment) {
+        perfdata.push(format!(
+            "deployment_elapsed={}s;;;0",
+            deployment.elapsed().as_secs()
+        ));
+        if let Some(progress) = deployment.progress {
+            perfdata.push(format!("deployment_progress={}%;;;0;100", progress));
+        }
+    }
+
+    let mut output = format!("{} | {}\n", plugin_text(&line), perfdata.join(" "));
+    if verbose {
+        for (name, result) in &status.checks {
+            let line = match &result.error {
+                None => format!("{}: OK", name),
+                Some(error) => format!("{}: CRITICAL - {}", name, error),
+            };
+            output.push_str(&plugin_text(&line));
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// Keeps text on a single line, and without the pipe that starts the performance data.
+fn plugin_text(text: &str) -> String {
+    text.replace(['\r', '\n'], " ").replace('|', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Deployment;
+
+    #[test]
+    fn test_preferred() {
+        assert_eq!(preferred(""), Format::Json);
+        assert_eq!(preferred("*/*"), Format::Json);
+        assert_eq!(preferred("text/plain"), Format::Text);
+        assert_eq!(preferred("application/json, text/html"), Format::Json);
+        assert_eq!(
+            preferred("text/plain;q=0.5, application/json;q=0.9"),
+            Format::Json
+        );
+        assert_eq!(
+            preferred("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Format::Html
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        let mut status = Status::new();
+        status.set_check_result("FileCheck", &Ok(()));
+        status.set_check_result("UrlCheck", &Err("Timeout".to_string()));
+        assert_eq!(text(&status, false), format!("{}\n", status));
+        assert_eq!(
+            text(&status, true),
+            format!("[+]FileCheck ok\n[-]UrlCheck failed: Timeout\n{}\n", status)
+        );
+    }
+
+    #[test]
+    fn test_nagios() {
+        let mut status = Status::new();
+        status.set_state(HealthState::Healthy);
+        status.set_phase(DeploymentPhase::Online);
+        assert_eq!(
+            nagios(&status, false),
+            "OK - healthy, online | checks=0;;;0 failed_checks=0;;;0 messages=0;;;0\n"
+        );
+
+        status.set_check_result("UrlCheck", &Err("Timeout | retrying".to_string()));
+        status.set_state(HealthState::Unhealthy);
+        status.add_message("UrlCheck: Timeout | retrying".to_string());
+        assert_eq!(
+            nagios(&status, true),
+            "CRITICAL - unhealthy, online: UrlCheck: Timeout / retrying | checks=1;;;0 failed_checks=1;;;0 messages=1;;;0\n\
+             UrlCheck: CRITICAL - Timeout / retrying\n"
+        );
+
+        let mut status = Status::new();
+        status.set_phase(DeploymentPhase::Maintenance);
+        assert!(nagios(&status, false).starts_with("WARNING - healthy, maintenance |"));
+
+        status.set_phase(DeploymentPhase::Deploying);
+        status.deployment = Some(Deployment {
+            progress: Some(40),
+            ..Deployment::new()
+        });
+        assert!(nagios(&status, false)
+            .ends_with(" deployment_elapsed=0s;;;0 deployment_progress=40%;;;0;100\n"));
+    }
+}
@@ -0,0 +1,197 @@
+/// This module implements the standard gRPC health checking protocol (`grpc.health.v1.Health`)
+/// on top of the health status, for load balancers and service meshes that only speak gRPC.
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use axum::Router;
+use log::debug;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::service::Routes;
+use tonic::{Request, Response, Status as GrpcStatus};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::pb::{HealthCheckRequest, HealthCheckResponse};
+
+/// The service names that report the health of the monitored application. The empty name is
+/// used by clients to request the overall health of the server.
+const SERVICE_NAMES: [&str; 2] = ["", env!("CARGO_PKG_NAME")];
+
+pub struct HealthService {
+    status: Arc<Mutex<Status>>,
+}
+
+impl HealthService {
+    pub fn new(status: Arc<Mutex<Status>>) -> Self {
+        HealthService { status }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, GrpcStatus> {
+        let service = request.into_inner().service;
+        debug!("Receive gRPC health check for service '{}'", service);
+        if !SERVICE_NAMES.contains(&service.as_str()) {
+            return Err(GrpcStatus::not_found(format!(
+                "Unknown service: {}",
+                service
+            )));
+        }
+
+        let status = serving_status(&*self.status.lock().await);
+        Ok(Response::new(HealthCheckResponse {
+            status: status.into(),
+        }))
+    }
+
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, GrpcStatus>> + Send + 'static>>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, GrpcStatus> {
+        let service = request.into_inner().service;
+        debug!("Receive gRPC health watch for service '{}'", service);
+        let (sender, receiver) = mpsc::channel(4);
+
+        // Unknown services are reported once, as the protocol requires, without ending the call.
+        if !SERVICE_NAMES.contains(&service.as_str()) {
+            let response = HealthCheckResponse {
+                status: ServingStatus::ServiceUnknown.into(),
+            };
+            let _ = sender.send(Ok(response)).await;
+            tokio::spawn(async move { sender.closed().await });
+            return Ok(Response::new(Box::pin(ReceiverStream::new(receiver))));
+        }
+
+        // Send the current status, and then a new message every time the serving status changes.
+        let status = self.status.clone();
+        let mut changes = status.lock().await.subscribe();
+        tokio::spawn(async move {
+            let mut last_sent = None;
+            loop {
+                let current = serving_status(&*status.lock().await);
+                if last_sent != Some(current) {
+                    let response = HealthCheckResponse {
+                        status: current.into(),
+                    };
+                    if sender.send(Ok(response)).await.is_err() {
+                        break;
+                    }
+                    last_sent = Some(current);
+                }
+                tokio::select! {
+                    changed = changes.changed() => if changed.is_err() { break },
+                    _ = sender.closed() => break,
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+/// Maps the health state and deployment phase to the gRPC serving status.
+fn serving_status(status: &Status) -> ServingStatus {
+    match (&status.phase, status.state) {
+        (DeploymentPhase::Draining | DeploymentPhase::Maintenance, _) => ServingStatus::NotServing,
+        (_, HealthState::Unhealthy) => ServingStatus::NotServing,
+        (DeploymentPhase::Deploying | DeploymentPhase::Online, HealthState::Healthy) => {
+            ServingStatus::Serving
+        }
+    }
+}
+
+/// Returns an axum router serving the gRPC health service, so it can be merged with the REST API
+/// or served on its own.
+pub fn create_router(status: Arc<Mutex<Status>>) -> Router {
+    Routes::new(HealthServer::new(HealthService::new(status))).into_axum_router()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn service() -> (HealthService, Arc<Mutex<Status>>) {
+        let status = Arc::new(Mutex::new(Status::new()));
+        (HealthService::new(status.clone()), status)
+    }
+
+    async fn check(service: &HealthService, name: &str) -> Result<ServingStatus, GrpcStatus> {
+        let request = Request::new(HealthCheckRequest {
+            service: name.to_string(),
+        });
+        let response = service.check(request).await?.into_inner();
+        Ok(ServingStatus::try_from(response.status).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let (service, status) = service();
+        assert_eq!(check(&service, "").await.unwrap(), ServingStatus::Serving);
+        assert_eq!(
+            check(&service, "healthmonitor").await.unwrap(),
+            ServingStatus::Serving
+        );
+
+        status.lock().await.set_phase(DeploymentPhase::Maintenance);
+        assert_eq!(
+            check(&service, "").await.unwrap(),
+            ServingStatus::NotServing
+        );
+
+        status.lock().await.set_phase(DeploymentPhase::Online);
+        status.lock().await.set_state(HealthState::Unhealthy);
+        assert_eq!(
+            check(&service, "").await.unwrap(),
+            ServingStatus::NotServing
+        );
+
+        let error = check(&service, "unknown").await.unwrap_err();
+        assert_eq!(error.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let (service, status) = service();
+        let request = Request::new(HealthCheckRequest {
+            service: "".to_string(),
+        });
+        let mut stream = service.watch(request).await.unwrap().into_inner();
+
+        let next = |response: Option<Result<HealthCheckResponse, GrpcStatus>>| {
+            ServingStatus::try_from(response.unwrap().unwrap().status).unwrap()
+        };
+        assert_eq!(next(stream.next().await), ServingStatus::Serving);
+
+        // Changes that don't affect the serving status are not streamed.
+        status.lock().await.add_message("Test message".to_string());
+        status.lock().await.set_state(HealthState::Unhealthy);
+        assert_eq!(next(stream.next().await), ServingStatus::NotServing);
+
+        status.lock().await.set_state(HealthState::Healthy);
+        assert_eq!(next(stream.next().await), ServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn test_watch_unknown_service() {
+        let (service, _status) = service();
+        let request = Request::new(HealthCheckRequest {
+            service: "unknown".to_string(),
+        });
+        let mut stream = service.watch(request).await.unwrap().into_inner();
+        let response = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            ServingStatus::try_from(response.status).unwrap(),
+            ServingStatus::ServiceUnknown
+        );
+    }
+}
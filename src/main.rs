@@ -1,25 +1,55 @@
+mod access;
+mod agent_check;
+mod audit;
+mod auth;
 mod checks;
 mod cli;
 mod client;
 mod config;
+mod dashboard;
+mod deploy;
+mod deployment;
+mod format;
+mod grpc;
+mod openapi;
+mod probes;
+mod problem;
+mod process;
+mod schema;
 mod server;
 mod status;
+mod supervisor;
+mod systemd;
+mod tls;
+mod wait;
+mod zabbix;
 
-use crate::checks::plugin_manager::PluginManager;
-use crate::status::{HealthState, Status};
+use crate::audit::{AuditLog, Source};
+use crate::checks::plugin_manager::{Checks, PluginManager};
+use crate::config::Config;
+use crate::deployment::DeploymentMonitor;
+use crate::status::{DeploymentPhase, DeploymentUpdate, HealthState, Status};
+use crate::supervisor::Supervisor;
+use crate::wait::Condition;
 
 use clap::Parser;
 use config::CONFIG;
 use dotenv::dotenv;
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use nix::sys::signal::Signal;
 use server::Server;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
 use std::process::exit;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 
 #[tokio::main]
 async fn main() {
+    ENVIRONMENT.get_or_init(|| env::vars().collect());
     dotenv().ok();
     env_logger::init();
 
@@ -70,6 +100,17 @@ async fn main() {
                     exit(1);
                 }
             },
+            Some(cli::StateCommands::Wait {
+                health_state,
+                timeout,
+            }) => {
+                let condition = Condition {
+                    state: Some(health_state.into()),
+                    phase: None,
+                };
+                let status = wait_for(&condition, timeout).await;
+                println!("{}", status.state);
+            }
             None => {}
         },
         Some(cli::Commands::Phase { command }) => match command {
@@ -82,8 +123,19 @@ async fn main() {
                     exit(1);
                 }
             },
-            Some(cli::PhaseCommands::Set { phase }) => {
-                match client::set_deployment_phase(phase.into()).await {
+            Some(cli::PhaseCommands::Set {
+                phase,
+                message,
+                release,
+                step,
+                progress,
+            }) => {
+                let deployment = DeploymentUpdate {
+                    step,
+                    progress,
+                    ..release.into()
+                };
+                match client::set_deployment_phase(phase.into(), message, deployment).await {
                     Ok(_) => {}
                     Err(e) => {
                         eprintln!("Failed to set phase: {}", e);
@@ -91,8 +143,57 @@ async fn main() {
                     }
                 }
             }
+            Some(cli::PhaseCommands::Wait { phase, timeout }) => {
+                let condition = Condition {
+                    state: None,
+                    phase: Some(phase.into()),
+                };
+                let status = wait_for(&condition, timeout).await;
+                println!("{}", status.phase);
+            }
+            Some(cli::PhaseCommands::History) => match client::get_deployments().await {
+                Ok(deployments) => {
+                    if !deployments.release.is_empty() {
+                        println!("running {}", deployments.release);
+                    }
+                    if let Some(deployment) = deployments.deployment {
+                        println!("{}", deployment);
+                    }
+                    for record in deployments.history.iter().rev() {
+                        println!("{}", record);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to get deployments: {}", e);
+                    exit(1);
+                }
+            },
             None => {}
         },
+        Some(cli::Commands::Deploy { release, command }) => {
+            let code = deploy::deploy(&command, release.into()).await;
+            if code != 0 {
+                exit(code);
+            }
+        }
+        Some(cli::Commands::Run {
+            restart,
+            max_restarts,
+            backoff,
+            command,
+        }) => {
+            let supervisor = Supervisor::new(
+                command,
+                restart.into(),
+                max_restarts,
+                Duration::from_secs(backoff),
+            );
+            match run_application(supervisor).await {
+                Ok(0) => return,
+                Ok(code) => exit(code),
+                Err(_) => exit(1),
+            }
+        }
         Some(cli::Commands::Check) => {
             let plugin_manager = PluginManager::new();
             let result = plugin_manager.quick_check().await;
@@ -112,30 +213,225 @@ async fn main() {
     debug!("Exiting.");
 }
 
+/// The exit code of the wait commands when the timeout expires, the same as timeout(1).
+const EXIT_TIMEOUT: i32 = 124;
+
+/// The process environment before the .env file was loaded into it.
+static ENVIRONMENT: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Waits until the status meets the condition and returns it. Exits if the timeout expires or the
+/// server can't be reached.
+async fn wait_for(condition: &Condition, timeout: Duration) -> Status {
+    match client::wait_for(condition, timeout).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            eprintln!(
+                "Timed out after {} seconds waiting for {}.",
+                timeout.as_secs(),
+                condition
+            );
+            exit(EXIT_TIMEOUT);
+        }
+        Err(e) => {
+            eprintln!("Failed to wait for {}: {}", condition, e);
+            exit(1);
+        }
+    }
+}
+
 async fn start_server() -> Result<(), ()> {
-    let status = Arc::new(Mutex::new(Status::new()));
-    let server = Arc::new(Mutex::new(Server::new(status.clone())));
+    serve(None).await.map(|_| ())
+}
+
+/// Starts the server and runs the monitored application under supervision. Returns the exit code
+/// of the application.
+async fn run_application(supervisor: Supervisor) -> Result<i32, ()> {
+    serve(Some(supervisor)).await
+}
 
-    // Start the server.
-    server.lock().await.start().await?;
+/// Runs the server and the health checks, and the supervised application if there is one, until
+/// we are asked to shut down. Returns the exit code of the application, or 0 if there is none.
+async fn serve(supervisor: Option<Supervisor>) -> Result<i32, ()> {
+    // Listen for signals before announcing that the server started, so none of them get lost.
+    let mut sigint = signal(SignalKind::interrupt()).unwrap();
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    let mut sighup = signal(SignalKind::hangup()).unwrap();
+    let mut sigusr1 = signal(SignalKind::user_defined1()).unwrap();
+
+    let mut status = Status::new();
+    status.audit = AuditLog::open(&CONFIG.audit).map_err(|e| error!("{}", e))?;
+    let status = Arc::new(Mutex::new(status));
+    let mut server = Server::new(status.clone());
+    server.start().await?;
 
     // Start the plugins that will monitor the health of the system.
-    let plugin_manager = PluginManager::new();
-    let status_clone = status.clone();
     debug!("Starting health monitoring.");
-    tokio::spawn(async move {
-        plugin_manager.monitor(status_clone).await;
+    let mut checks = PluginManager::new().monitor(status.clone()).await;
+
+    // Tell systemd when we are ready and keep it up to date, if it is supervising us.
+    let notifier = systemd::Notifier::from_env().map(Arc::new);
+    let notifications = notifier.clone().map(|notifier| {
+        tokio::spawn(systemd::run(
+            notifier,
+            status.clone(),
+            systemd::watchdog_from_env(),
+        ))
     });
 
-    // Keep the server running alongside the monitoring threads until a Ctrl+C signal is received.
-    let mut sigint = signal(SignalKind::interrupt()).unwrap();
-    debug!("Waiting for SIGINT signal.");
-    tokio::select! {
-        _ = sigint.recv() => {
-            info!("Received SIGINT, shutting down.");
-            let mut srv = server.lock().await;
-            srv.stop().await;
-            Ok(())
+    // The supervised application is stopped by forwarding the signal we received, after the
+    // grace period.
+    let (signals, received) = mpsc::unbounded_channel();
+    let supervised = supervisor.is_some();
+    let application = async {
+        match &supervisor {
+            Some(supervisor) => supervisor.run(status.clone(), received).await,
+            None => std::future::pending().await,
         }
+    };
+    tokio::pin!(application);
+
+    // Follow the deployment phase, to act on phases that are limited in time.
+    let deployment_monitor = DeploymentMonitor::new(&CONFIG);
+    let drained = deployment_monitor.run(status.clone());
+    tokio::pin!(drained);
+
+    let mut stopping = false;
+    debug!("Waiting for signals.");
+    let code = loop {
+        let stop = tokio::select! {
+            code = &mut application => break code,
+            _ = sigint.recv() => {
+                info!("Received SIGINT, shutting down.");
+                Signal::SIGINT
+            }
+            _ = sigterm.recv() => {
+                info!("Received SIGTERM, shutting down.");
+                if !stopping {
+                    drain(&status, &mut sigint, &mut sigterm).await;
+                }
+                Signal::SIGTERM
+            }
+            _ = &mut drained, if !stopping => {
+                info!("Application has been drained, shutting down.");
+                Signal::SIGTERM
+            }
+            _ = sighup.recv() => {
+                info!("Received SIGHUP, reloading the health checks.");
+                checks.stop().await;
+                checks = reload(&status).await;
+                continue;
+            }
+            _ = sigusr1.recv() => {
+                dump(&status).await;
+                continue;
+            }
+        };
+        stopping = true;
+        if !supervised {
+            break 0;
+        }
+        // Keep running until the application exits. A second signal will kill it.
+        let _ = signals.send(stop);
+    };
+
+    if let (Some(notifier), Some(notifications)) = (notifier, notifications) {
+        notifications.abort();
+        notifier.notify("STOPPING=1");
+    }
+
+    // Stop accepting connections, then wait for the checks that are running to finish.
+    server.stop().await;
+    checks.stop().await;
+    // Write the queued audit records before the process exits.
+    status.lock().await.audit.close();
+    Ok(code)
+}
+
+/// Puts the instance in the "draining" phase for the grace period, so load balancers notice it is
+/// going away before it stops accepting connections. Another signal ends the grace period early.
+///
+/// Shutting down follows the same phase transitions as the API. An instance in maintenance is
+/// already out of rotation, and one that is being deployed can't be drained, so those shut down
+/// without a grace period.
+async fn drain(
+    status: &Arc<Mutex<Status>>,
+    sigint: &mut tokio::signal::unix::Signal,
+    sigterm: &mut tokio::signal::unix::Signal,
+) {
+    let grace_period = Duration::from_secs(CONFIG.server.shutdown_grace_period as u64);
+    if grace_period.is_zero() {
+        return;
     }
+
+    {
+        let mut status = status.lock().await;
+        if !status.phase.can_transition_to(&DeploymentPhase::Draining) {
+            info!(
+                "Not draining before shutting down, the application is {}.",
+                status.phase
+            );
+            return;
+        }
+        let before = status.snapshot();
+        status.set_phase(DeploymentPhase::Draining);
+        let source = Source::System {
+            name: "shutdown".to_string(),
+        };
+        status.audit(before, source, None);
+    }
+    info!("Draining for {:?} before shutting down.", grace_period);
+    tokio::select! {
+        _ = tokio::time::sleep(grace_period) => {}
+        _ = sigint.recv() => info!("Received SIGINT, ending the grace period."),
+        _ = sigterm.recv() => info!("Received SIGTERM, ending the grace period."),
+    }
+}
+
+/// Reloads the configuration of the health checks from the environment and the .env file, and
+/// restarts them. The other settings, such as the ports, are only read on startup.
+async fn reload(status: &Arc<Mutex<Status>>) -> Checks {
+    // Like on startup, the .env file doesn't override the process environment. That is the
+    // environment from before the file was loaded into it, so changes to the file are picked up.
+    // The process environment is left alone, changing it while other threads run is unsound.
+    let mut vars = HashMap::new();
+    match env_file() {
+        Some(path) => match config::read_env_file(&path) {
+            Ok(file) => vars = file,
+            // Keep the values that were loaded on startup.
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                vars = env::vars().collect();
+            }
+        },
+        None => debug!("Not reloading the .env file: it doesn't exist."),
+    }
+    vars.extend(ENVIRONMENT.get().cloned().unwrap_or_default());
+    let config = Config::from_vars(&vars);
+    debug!("Reloaded config: {:?}", config);
+    PluginManager::with_config(&config)
+        .monitor(status.clone())
+        .await
+}
+
+/// Returns the path of the .env file that was loaded on startup: the first one found in the
+/// working directory or one of its parents.
+fn env_file() -> Option<PathBuf> {
+    let directory = env::current_dir().ok()?;
+    directory
+        .ancestors()
+        .map(|directory| directory.join(".env"))
+        .find(|path| path.is_file())
+}
+
+/// Writes the current status to the log.
+async fn dump(status: &Arc<Mutex<Status>>) {
+    let status = status.lock().await;
+    info!(
+        "Status: {}",
+        serde_json::to_string(&*status).unwrap_or_default()
+    );
+    info!(
+        "Deployments: {}",
+        serde_json::to_string(&status.deployments()).unwrap_or_default()
+    );
 }
@@ -0,0 +1,294 @@
+/// This module describes the REST API as an OpenAPI 3.1 document, which is served at
+/// `/openapi.json`. The schemas of the request and response bodies are the ones served at
+/// `/schema`, so both descriptions stay in sync.
+use crate::schema;
+
+use serde_json::{json, Value};
+
+/// Returns the OpenAPI document of the REST API.
+pub fn openapi() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "HealthMonitor",
+            "description": "Reports and changes the health state and deployment phase of the monitored application.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths(),
+        "components": {
+            "schemas": components(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        // Tokens are only required when they are configured.
+        "security": [{ "bearer": [] }, {}],
+    })
+}
+
+/// Returns the schema definitions, with the references pointing to the OpenAPI components.
+fn components() -> Value {
+    let definitions = schema::definitions().to_string();
+    serde_json::from_str(&definitions.replace("#/$defs/", "#/components/schemas/"))
+        .unwrap_or_default()
+}
+
+fn paths() -> Value {
+    json!({
+        "/status": {
+            "get": {
+                "summary": "Get the health status of the monitored application.",
+                "operationId": "getStatus",
+                "parameters": [
+                    query("format", "The format of the response: json, text, nagios or html. Overrides the Accept header."),
+                    {
+                        "name": "verbose",
+                        "in": "query",
+                        "required": false,
+                        "description": "Whether to include the result of every check in the text and nagios formats.",
+                        "schema": { "type": "boolean" },
+                    },
+                    header("If-None-Match", "The ETag of the JSON status the client has."),
+                ],
+                "responses": {
+                    "200": status_response("The application is healthy and serving."),
+                    "304": { "description": "The status did not change." },
+                    "400": problem_response("The format is invalid."),
+                    "503": status_response("The application is unhealthy, draining or in maintenance."),
+                },
+            },
+            "head": {
+                "summary": "Get only the status code of the health status.",
+                "operationId": "headStatus",
+                "responses": {
+                    "200": { "description": "The application is healthy and serving." },
+                    "503": { "description": "The application is unhealthy, draining or in maintenance." },
+                },
+            },
+            "patch": {
+                "summary": "Change the health state or the deployment phase.",
+                "operationId": "patchStatus",
+                "parameters": [header(
+                    "If-Match",
+                    "Only change the status if its ETag still matches, i.e. it didn't change since the client read it.",
+                )],
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": reference("StatusPatch") } },
+                },
+                "responses": {
+                    "200": text_response("The status was updated."),
+                    "400": problem_response("The request is invalid, nothing was changed."),
+                    "409": problem_response("The deployment phase can not be changed to the requested phase."),
+                    "412": problem_response("The status changed since the client read it, nothing was changed."),
+                },
+            },
+        },
+        "/status/events": {
+            "get": {
+                "summary": "Stream the status as server-sent events, whenever it changes.",
+                "operationId": "getStatusEvents",
+                "responses": {
+                    "200": {
+                        "description": "A `status` event with the status for every revision, starting with the current one.",
+                        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                    },
+                },
+            },
+        },
+        "/status/wait": {
+            "get": {
+                "summary": "Wait until the status has the given health state and deployment phase.",
+                "operationId": "waitStatus",
+                "parameters": [
+                    query("state", "The health state to wait for: healthy or unhealthy."),
+                    query("phase", "The deployment phase to wait for."),
+                    query("timeout", "How long to wait, e.g. 300s, 5m or 1h. Defaults to 30s, at most 1h."),
+                ],
+                "responses": {
+                    "200": json_response("The status meets the condition.", "Status"),
+                    "400": problem_response("A parameter is invalid."),
+                    "408": problem_response("The status did not meet the condition before the timeout."),
+                    "503": problem_response("The server shut down before the status met the condition."),
+                },
+            },
+        },
+        "/livez": probe("Liveness probe: whether the health monitor itself is responding."),
+        "/readyz": probe("Readiness probe: whether the application should receive traffic."),
+        "/startupz": probe("Startup probe: whether the first round of health checks has completed."),
+        "/deployments": {
+            "get": {
+                "summary": "Get the running release, the deployment in progress and the past deployments.",
+                "operationId": "getDeployments",
+                "responses": {
+                    "200": json_response("The deployments.", "Deployments"),
+                },
+            },
+        },
+        "/audit": {
+            "get": {
+                "summary": "Get the recent changes to the health state and the deployment phase, oldest first.",
+                "operationId": "getAudit",
+                "responses": {
+                    "200": {
+                        "description": "The audit records.",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": reference("AuditRecord") },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+        "/info": {
+            "get": {
+                "summary": "Get the name and version of the health monitor.",
+                "operationId": "getInfo",
+                "responses": {
+                    "200": json_response("The name and version.", "Info"),
+                },
+            },
+        },
+        "/schema": {
+            "get": {
+                "summary": "Get the JSON Schema of the documents of the API.",
+                "operationId": "getSchema",
+                "responses": {
+                    "200": { "description": "The JSON Schema.", "content": { "application/json": {} } },
+                },
+            },
+        },
+        "/": dashboard("Get the HTML dashboard.", "getIndex"),
+        "/dashboard": dashboard("Get the HTML dashboard, which follows the status live.", "getDashboard"),
+        "/openapi.json": {
+            "get": {
+                "summary": "Get this document.",
+                "operationId": "getOpenApi",
+                "responses": {
+                    "200": { "description": "The OpenAPI document.", "content": { "application/json": {} } },
+                },
+            },
+        },
+    })
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn header(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "header",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": reference(schema) } },
+    })
+}
+
+/// Returns a response with the status, in each of the formats it is available in: JSON, the
+/// one-line text or Nagios plugin output, and the dashboard for browsers.
+fn status_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": { "schema": reference("Status") },
+            "text/plain": { "schema": { "type": "string" } },
+            "text/html": {},
+        },
+    })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+fn problem_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { crate::problem::CONTENT_TYPE: { "schema": reference("Problem") } },
+    })
+}
+
+fn query(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+/// Returns the description of an endpoint that serves the dashboard.
+fn dashboard(summary: &str, operation_id: &str) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "operationId": operation_id,
+            // The page itself doesn't need a token, it sends the token from its URL fragment.
+            "security": [],
+            "responses": {
+                "200": { "description": "The dashboard.", "content": { "text/html": {} } },
+            },
+        },
+    })
+}
+
+/// Returns the description of a probe endpoint.
+fn probe(summary: &str) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": [
+                query("include", "Comma separated list of components to evaluate."),
+                query("exclude", "Comma separated list of components to skip."),
+                {
+                    "name": "verbose",
+                    "in": "query",
+                    "required": false,
+                    "description": "Whether to list the result of each component. Only requests with a valid token get them, when tokens are configured.",
+                    "schema": { "type": "boolean" },
+                },
+            ],
+            // The probes don't need a token.
+            "security": [],
+            "responses": {
+                "200": text_response("The probe passes."),
+                "503": text_response("The probe fails."),
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references() {
+        // Every reference points to a component.
+        let document = openapi();
+        let text = document.to_string();
+        let components = document["components"]["schemas"].as_object().unwrap();
+        assert!(!text.contains("#/$defs/"));
+        for (index, _) in text.match_indices("#/components/schemas/") {
+            let name: String = text[index + "#/components/schemas/".len()..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric())
+                .collect();
+            assert!(components.contains_key(&name), "Missing component {}", name);
+        }
+    }
+}
@@ -0,0 +1,340 @@
+/// This module implements the liveness, readiness and startup probes. Each probe consists of a
+/// number of named components, which can be included or excluded using query parameters, e.g.
+/// `/readyz?exclude=UrlCheck` or `/readyz?include=phase,FileCheck&verbose=true`.
+use crate::config::CONFIG;
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Probe {
+    /// Whether the health monitor itself is alive.
+    Liveness,
+    /// Whether the monitored application should receive traffic.
+    Readiness,
+    /// Whether the first round of health checks has completed.
+    Startup,
+}
+
+impl Probe {
+    fn name(&self) -> &'static str {
+        match self {
+            Probe::Liveness => "livez",
+            Probe::Readiness => "readyz",
+            Probe::Startup => "startupz",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ProbeQuery {
+    /// Comma separated list of components to evaluate. All components are evaluated if omitted.
+    pub include: Option<String>,
+    /// Comma separated list of components to skip.
+    pub exclude: Option<String>,
+    /// Whether to list the result of each component, also when the probe passes.
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+impl ProbeQuery {
+    fn names(list: &Option<String>) -> Vec<&str> {
+        list.as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+}
+
+/// The result of a single component of a probe.
+#[derive(Debug, PartialEq)]
+struct Component {
+    name: String,
+    error: Option<String>,
+}
+
+impl Component {
+    fn new(name: &str, error: Option<String>) -> Self {
+        Component {
+            name: name.to_string(),
+            error,
+        }
+    }
+}
+
+/// Returns the components of the given probe, evaluated against the current status.
+fn components(probe: Probe, status: &Status) -> Vec<Component> {
+    match probe {
+        Probe::Liveness => vec![Component::new("ping", None)],
+        Probe::Readiness => {
+            let phase_error = match status.phase {
+                DeploymentPhase::Online => None,
+                DeploymentPhase::Deploying if CONFIG.server.ready_while_deploying => None,
+                DeploymentPhase::Deploying => Some("the application is being deployed".to_string()),
+                DeploymentPhase::Draining => Some("the application is draining".to_string()),
+                DeploymentPhase::Maintenance => {
+                    Some("the application is in maintenance".to_string())
+                }
+            };
+            // A check that fails marks the application as unhealthy with a message. Its failure
+            // is reported by the component of the check, so it can be excluded. The health
+            // component reports the other reasons, such as a check that failed before.
+            let failing: Vec<String> = status
+                .checks
+                .iter()
+                .filter(|(_, result)| !result.passed)
+                .map(|(name, _)| format!("{}: ", name))
+                .collect();
+            let reasons: Vec<&str> = status
+                .messages
+                .iter()
+                .filter(|message| !failing.iter().any(|prefix| message.starts_with(prefix)))
+                .map(String::as_str)
+                .collect();
+            let health_error = match status.state {
+                HealthState::Healthy => None,
+                HealthState::Unhealthy if !reasons.is_empty() => Some(reasons.join(", ")),
+                HealthState::Unhealthy if !failing.is_empty() => None,
+                HealthState::Unhealthy => Some("the application is unhealthy".to_string()),
+            };
+            let mut components = vec![
+                Component::new("phase", phase_error),
+                Component::new("health", health_error),
+            ];
+            // The results of the individual health checks are part of the readiness probe.
+            components.extend(status.checks.iter().map(|(name, result)| {
+                let error = (!result.passed).then(|| {
+                    result
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| "check failed".to_string())
+                });
+                Component::new(name, error)
+            }));
+            components
+        }
+        // A check that fails later on doesn't mean the application failed to start.
+        Probe::Startup => {
+            let startup_error = (!status.startup_complete)
+                .then(|| "the first round of health checks has not completed".to_string());
+            vec![Component::new("startup", startup_error)]
+        }
+    }
+}
+
+/// Evaluates the probe against the current status. Without `details` only the verdict is returned,
+/// for clients that may not see the errors of the checks.
+pub async fn probe(
+    probe: Probe,
+    status: Arc<Mutex<Status>>,
+    query: ProbeQuery,
+    details: bool,
+) -> Response {
+    let all = components(probe, &*status.lock().await);
+    let include = ProbeQuery::names(&query.include);
+    let exclude = ProbeQuery::names(&query.exclude);
+
+    let mut selected: Vec<Component> = if include.is_empty() {
+        all
+    } else {
+        // Explicitly included components for which there is no result, such as checks that did
+        // not run yet, are reported as failing.
+        include
+            .iter()
+            .map(|name| {
+                all.iter()
+                    .find(|component| component.name == *name)
+                    .map(|component| Component::new(name, component.error.clone()))
+                    .unwrap_or_else(|| Component::new(name, Some("no result".to_string())))
+            })
+            .collect()
+    };
+    selected.retain(|component| !exclude.contains(&component.name.as_str()));
+
+    let passed = selected.iter().all(|component| component.error.is_none());
+    let (status_code, verdict) = if passed {
+        (StatusCode::OK, "ok".to_string())
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{} check failed", probe.name()),
+        )
+    };
+
+    if !details || (passed && !query.verbose) {
+        return (status_code, verdict).into_response();
+    }
+
+    let mut body = String::new();
+    for component in &selected {
+        match &component.error {
+            None => body.push_str(&format!("[+]{} ok\n", component.name)),
+            Some(error) => body.push_str(&format!("[-]{} failed: {}\n", component.name, error)),
+        }
+    }
+    body.push_str(&verdict);
+    (status_code, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    async fn evaluate(
+        probe_type: Probe,
+        status: &Arc<Mutex<Status>>,
+        query: ProbeQuery,
+    ) -> (StatusCode, String) {
+        let response = probe(probe_type, status.clone(), query, true).await;
+        let status_code = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status_code, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn query(include: Option<&str>, exclude: Option<&str>, verbose: bool) -> ProbeQuery {
+        ProbeQuery {
+            include: include.map(str::to_string),
+            exclude: exclude.map(str::to_string),
+            verbose,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_liveness() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let (code, body) = evaluate(Probe::Liveness, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (code, body) = evaluate(Probe::Readiness, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "ok");
+
+        let (code, body) = evaluate(Probe::Readiness, &status, query(None, None, true)).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "[+]phase ok\n[+]health ok\nok");
+
+        status.lock().await.set_state(HealthState::Unhealthy);
+        status
+            .lock()
+            .await
+            .add_message("Database is down".to_string());
+        let (code, body) = evaluate(Probe::Readiness, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body,
+            "[+]phase ok\n[-]health failed: Database is down\nreadyz check failed"
+        );
+
+        // Clients that may not see the details only get the verdict.
+        let response = probe(
+            Probe::Readiness,
+            status.clone(),
+            query(None, None, true),
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "readyz check failed");
+    }
+
+    #[tokio::test]
+    async fn test_readiness_with_checks() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        status.lock().await.set_check_result("FileCheck", &Ok(()));
+        status
+            .lock()
+            .await
+            .set_check_result("UrlCheck", &Err("URL returned status 500".to_string()));
+
+        let (code, body) = evaluate(Probe::Readiness, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("[+]FileCheck ok\n"));
+        assert!(body.contains("[-]UrlCheck failed: URL returned status 500\n"));
+
+        let (code, _) = evaluate(
+            Probe::Readiness,
+            &status,
+            query(None, Some("UrlCheck"), false),
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK);
+
+        // The failing check also marks the application as unhealthy. It can still be excluded.
+        {
+            let mut status = status.lock().await;
+            status.set_state(HealthState::Unhealthy);
+            status.add_message("UrlCheck: URL returned status 500".to_string());
+        }
+        let (code, body) = evaluate(
+            Probe::Readiness,
+            &status,
+            query(None, Some("UrlCheck"), true),
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "[+]phase ok\n[+]health ok\n[+]FileCheck ok\nok");
+
+        // Once the check passes again, its earlier failure is a reason to stay unhealthy.
+        status.lock().await.set_check_result("UrlCheck", &Ok(()));
+        let (code, body) = evaluate(Probe::Readiness, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("[-]health failed: UrlCheck: URL returned status 500\n"));
+        status.lock().await.set_state(HealthState::Healthy);
+        status
+            .lock()
+            .await
+            .set_check_result("UrlCheck", &Err("URL returned status 500".to_string()));
+
+        let (code, body) = evaluate(
+            Probe::Readiness,
+            &status,
+            query(Some("phase,FileCheck"), None, true),
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "[+]phase ok\n[+]FileCheck ok\nok");
+
+        let (code, body) = evaluate(
+            Probe::Readiness,
+            &status,
+            query(Some("GrpcCheck"), None, false),
+        )
+        .await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "[-]GrpcCheck failed: no result\nreadyz check failed");
+    }
+
+    #[tokio::test]
+    async fn test_startup() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (code, _) = evaluate(Probe::Startup, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+
+        status.lock().await.complete_startup();
+        let (code, _) = evaluate(Probe::Startup, &status, ProbeQuery::default()).await;
+        assert_eq!(code, StatusCode::OK);
+
+        // Checks that fail after the first round don't affect it.
+        status
+            .lock()
+            .await
+            .set_check_result("UrlCheck", &Err("Timeout".to_string()));
+        let (code, body) = evaluate(Probe::Startup, &status, query(None, None, true)).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, "[+]startup ok\nok");
+    }
+}
@@ -0,0 +1,47 @@
+/// This module reports errors of the REST API as problem details (RFC 9457), a small JSON document
+/// with the `application/problem+json` content type. Besides a description of the problem it can
+/// name the field of the request that caused it.
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Problem {
+    /// The reason phrase of the status code, e.g. "Bad Request".
+    pub title: String,
+    pub status: u16,
+    /// What went wrong, for humans.
+    pub detail: String,
+    /// The field of the request body that is invalid, e.g. "phase" or "deployment.progress".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Problem {
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            field: None,
+        }
+    }
+
+    /// A problem with the given field of the request body.
+    pub fn field(status: StatusCode, field: &str, detail: impl Into<String>) -> Self {
+        Problem {
+            field: Some(field.to_string()),
+            ..Problem::new(status, detail)
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_REQUEST);
+        let body = serde_json::to_string(&self).unwrap_or_default();
+        (status, [(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response()
+    }
+}
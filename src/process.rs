@@ -0,0 +1,178 @@
+/// This module contains helpers for running child processes, shared by the deploy command and the
+/// supervisor.
+use log::{debug, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::VecDeque;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Keeps the last lines of the output of a child process.
+#[derive(Clone)]
+pub struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl OutputTail {
+    pub fn new(capacity: usize) -> Self {
+        OutputTail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity + 1))),
+            capacity,
+        }
+    }
+
+    /// Copies the lines of the given output to the echo writer while keeping the last lines. The
+    /// returned task finishes when the output is closed.
+    ///
+    /// The output is read to the end, even if it is not valid UTF-8 or the echo writer fails.
+    /// Closing the pipe early would kill the child process with SIGPIPE on its next write.
+    pub fn capture<R, W>(&self, output: R, mut echo: W) -> JoinHandle<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let tail = self.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(output);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line).await {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to read the output: {}", e);
+                        break;
+                    }
+                }
+                let _ = echo.write_all(&line).await;
+                let _ = echo.flush().await;
+                let text = String::from_utf8_lossy(&line);
+                tail.push(text.trim_end_matches(['\n', '\r']).to_string());
+            }
+        })
+    }
+
+    pub fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        lines.push_back(line);
+        if lines.len() > self.capacity {
+            lines.pop_front();
+        }
+    }
+
+    /// Returns the last lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+    }
+}
+
+/// How long to wait for the rest of the output of a process after it exited.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Waits for the captures of a process that exited to read the rest of its output, for at most the
+/// given time. A process it started in the background can keep the output open long after, so the
+/// captures that are still running are left to finish on their own.
+pub async fn drain(captures: Vec<JoinHandle<()>>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    for capture in captures {
+        if tokio::time::timeout_at(deadline, capture).await.is_err() {
+            debug!("The output is still open, not waiting for it to be closed.");
+        }
+    }
+}
+
+/// Returns the exit code of a process, following the shell convention for processes that were
+/// killed by a signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// Sends a signal to the child process.
+pub fn forward(child: &Child, signal: Signal) {
+    if let Some(id) = child.id() {
+        if let Err(e) = kill(Pid::from_raw(id as i32), signal) {
+            warn!("Failed to forward {} to {}: {}", signal, id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use tokio::process::Command;
+
+    #[tokio::test]
+    async fn test_output_tail() {
+        let mut child = Command::new("sh")
+            .args(["-c", "for i in 1 2 3 4; do echo Line $i; done"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let tail = OutputTail::new(2);
+        let capture = tail.capture(child.stdout.take().unwrap(), tokio::io::sink());
+        child.wait().await.unwrap();
+        capture.await.unwrap();
+        assert_eq!(tail.lines(), vec!["Line 3", "Line 4"]);
+
+        tail.clear();
+        assert!(tail.lines().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_output_tail_invalid_utf8() {
+        // The process keeps writing after a line that is not valid UTF-8.
+        let mut child = Command::new("sh")
+            .args(["-c", "printf 'Caf\\351\\n'; sleep 0.1; echo Done"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let tail = OutputTail::new(2);
+        let capture = tail.capture(child.stdout.take().unwrap(), tokio::io::sink());
+        assert!(child.wait().await.unwrap().success());
+        capture.await.unwrap();
+        assert_eq!(tail.lines(), vec!["Caf\u{FFFD}", "Done"]);
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        // A process in the background keeps the output open.
+        let mut child = Command::new("sh")
+            .args(["-c", "echo Started; sleep 30 &"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let tail = OutputTail::new(2);
+        let capture = tail.capture(child.stdout.take().unwrap(), tokio::io::sink());
+        child.wait().await.unwrap();
+        let drained = drain(vec![capture], Duration::from_millis(100));
+        tokio::time::timeout(Duration::from_secs(1), drained)
+            .await
+            .unwrap();
+        assert_eq!(tail.lines(), vec!["Started"]);
+    }
+
+    #[tokio::test]
+    async fn test_exit_code() {
+        let status = Command::new("sh").args(["-c", "exit 3"]).status();
+        assert_eq!(exit_code(status.await.unwrap()), 3);
+
+        let status = Command::new("sh").args(["-c", "kill -9 $$"]).status();
+        assert_eq!(exit_code(status.await.unwrap()), 137);
+    }
+}
@@ -0,0 +1,237 @@
+/// This module describes the JSON documents of the REST API as a JSON Schema, which is served at
+/// `/schema`: the status that is returned by `GET /status`, the body of `PATCH /status`, and the
+/// documents of the other endpoints. The OpenAPI description reuses the definitions.
+use serde_json::{json, Value};
+
+/// Returns the JSON Schema with the definitions of the documents of the API.
+pub fn schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": "/schema",
+        "title": "HealthMonitor",
+        "$defs": definitions(),
+    })
+}
+
+/// Returns the schemas of the documents, keyed by name. References between them point to
+/// `#/$defs/`.
+pub fn definitions() -> Value {
+    json!({
+        "HealthState": {
+            "type": "string",
+            "enum": ["healthy", "unhealthy"],
+        },
+        "DeploymentPhase": {
+            "type": "string",
+            "enum": ["deploying", "online", "draining", "maintenance"],
+        },
+        "Release": {
+            "type": "object",
+            "properties": release_properties(),
+        },
+        "CheckResult": {
+            "type": "object",
+            "properties": {
+                "passed": { "type": "boolean" },
+                "last_run": { "type": "string", "format": "date-time" },
+                "error": { "type": "string" },
+            },
+            "required": ["passed", "last_run"],
+        },
+        "Deployment": {
+            "type": "object",
+            "properties": merge(release_properties(), json!({
+                "started": { "type": "string", "format": "date-time" },
+                "elapsed": { "type": "integer", "minimum": 0, "description": "Seconds since the deployment started." },
+                "step": { "type": "string" },
+                "progress": { "type": "integer", "minimum": 0, "maximum": 100 },
+            })),
+            "required": ["started", "elapsed"],
+        },
+        "Status": {
+            "type": "object",
+            "properties": {
+                "state": { "$ref": "#/$defs/HealthState" },
+                "messages": { "type": "array", "items": { "type": "string" } },
+                "phase": { "$ref": "#/$defs/DeploymentPhase" },
+                "checks": {
+                    "type": "object",
+                    "additionalProperties": { "$ref": "#/$defs/CheckResult" },
+                },
+                "deployment": { "$ref": "#/$defs/Deployment" },
+                "release": { "$ref": "#/$defs/Release" },
+                "revision": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Increases with every change of the state, the phase, the messages or the deployment.",
+                },
+            },
+            "required": ["state", "messages", "phase", "revision"],
+        },
+        "DeploymentUpdate": {
+            "type": "object",
+            "properties": merge(release_properties(), json!({
+                "step": { "type": "string" },
+                "progress": { "type": "integer", "minimum": 0, "maximum": 100 },
+            })),
+            "additionalProperties": false,
+        },
+        "DeploymentOutcome": {
+            "type": "string",
+            "enum": ["succeeded", "failed", "aborted"],
+        },
+        "DeploymentRecord": {
+            "type": "object",
+            "properties": merge(release_properties(), json!({
+                "started": { "type": "string", "format": "date-time" },
+                "finished": { "type": "string", "format": "date-time" },
+                "duration": { "type": "integer", "minimum": 0, "description": "The duration of the deployment in seconds." },
+                "outcome": { "$ref": "#/$defs/DeploymentOutcome" },
+            })),
+            "required": ["started", "finished", "duration", "outcome"],
+        },
+        "Deployments": {
+            "type": "object",
+            "properties": {
+                "release": { "$ref": "#/$defs/Release" },
+                "deployment": { "$ref": "#/$defs/Deployment" },
+                "history": { "type": "array", "items": { "$ref": "#/$defs/DeploymentRecord" } },
+            },
+            "required": ["release", "history"],
+        },
+        "Source": {
+            "type": "object",
+            "properties": {
+                "type": { "type": "string", "enum": ["cli", "api", "check", "system"] },
+                "client": { "type": "string", "description": "The IP address of the client that made the request." },
+                "token": { "type": "string", "description": "The ID of the token of the request." },
+                "name": { "type": "string", "description": "The name of the check or of the component." },
+            },
+            "required": ["type"],
+        },
+        "AuditRecord": {
+            "type": "object",
+            "properties": {
+                "timestamp": { "type": "string", "format": "date-time" },
+                "request_id": { "type": "string" },
+                "source": { "$ref": "#/$defs/Source" },
+                "state": change("#/$defs/HealthState"),
+                "phase": change("#/$defs/DeploymentPhase"),
+                "message": { "type": "string" },
+            },
+            "required": ["timestamp", "source"],
+        },
+        "Info": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "version": { "type": "string" },
+            },
+            "required": ["name", "version"],
+        },
+        "Problem": {
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "status": { "type": "integer" },
+                "detail": { "type": "string" },
+                "field": { "type": "string", "description": "The field of the request body that is invalid." },
+            },
+            "required": ["title", "status", "detail"],
+        },
+        "StatusPatch": {
+            "type": "object",
+            "properties": {
+                "health": { "$ref": "#/$defs/HealthState" },
+                "message": { "type": "string" },
+                "phase": { "$ref": "#/$defs/DeploymentPhase" },
+                "deployment": { "$ref": "#/$defs/DeploymentUpdate" },
+            },
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn release_properties() -> Value {
+    json!({
+        "version": { "type": "string" },
+        "sha": { "type": "string", "description": "The git commit that was deployed." },
+        "deployer": { "type": "string", "description": "The person or system that performed the deployment." },
+    })
+}
+
+/// Returns the schema of the old and new value of a field that changed.
+fn change(reference: &str) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "old": { "$ref": reference },
+            "new": { "$ref": reference },
+        },
+        "required": ["old", "new"],
+    })
+}
+
+/// Returns the properties of both objects.
+fn merge(mut a: Value, b: Value) -> Value {
+    if let (Some(a), Value::Object(b)) = (a.as_object_mut(), b) {
+        a.extend(b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{Deployment, Release, Status};
+
+    /// Returns the names of the properties of a definition.
+    fn properties(name: &str) -> Vec<String> {
+        let mut properties: Vec<String> = definitions()[name]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        properties.sort();
+        properties
+    }
+
+    fn keys(value: Value) -> Vec<String> {
+        let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_status_schema() {
+        // A status with every optional field set has all the properties of the schema.
+        let mut status = Status::new();
+        status.set_check_result("FileCheck", &Err("Missing".to_string()));
+        status.deployment = Some(Deployment {
+            release: Release {
+                version: Some("1.2.0".to_string()),
+                sha: Some("8f3c2a1".to_string()),
+                deployer: Some("jenkins".to_string()),
+            },
+            step: Some("database updates".to_string()),
+            progress: Some(40),
+            ..Deployment::new()
+        });
+        status.release.version = Some("1.1.0".to_string());
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(keys(json.clone()), properties("Status"));
+        assert_eq!(keys(json["deployment"].clone()), properties("Deployment"));
+        assert_eq!(
+            keys(json["checks"]["FileCheck"].clone()),
+            properties("CheckResult")
+        );
+    }
+
+    #[test]
+    fn test_patch_schema() {
+        let mut fields = crate::server::PATCH_FIELDS.map(String::from).to_vec();
+        fields.sort();
+        assert_eq!(fields, properties("StatusPatch"));
+    }
+}
@@ -1,31 +1,73 @@
+use crate::access::{self, AccessPolicy, ClientAddr};
+use crate::agent_check;
+use crate::audit::{AuditRecord, Source};
+use crate::auth::{self, Anonymous, Token, Tokens};
 use crate::client;
 use crate::config::CONFIG;
-use crate::status::{DeploymentPhase, HealthState, Status};
+use crate::dashboard;
+use crate::format::{self, Format, StatusQuery};
+use crate::grpc;
+use crate::openapi;
+use crate::probes::{self, Probe, ProbeQuery};
+use crate::problem::Problem;
+use crate::schema;
+use crate::status::{DeploymentPhase, DeploymentUpdate, HealthState, Status, StatusPatch};
+use crate::tls;
+use crate::wait::{self, WaitQuery};
+use crate::zabbix;
 
-use axum::http::StatusCode;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::Query;
+use axum::http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::{
-    routing::{get, patch},
+    routing::{get, patch, MethodRouter},
     Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
 use log::{debug, error, info, warn};
+use nix::unistd::{Group, User};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
 use tower_http::trace::TraceLayer;
 
+/// The header that identifies a request in the audit log.
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// How long to wait for in-flight requests to complete when stopping the server.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Server {
-    handle: Option<JoinHandle<()>>,
+    /// The HTTP servers, which are shut down gracefully.
+    handles: Vec<JoinHandle<()>>,
+    /// The listeners for the agent protocols, which answer immediately and are simply aborted.
+    listeners: Vec<JoinHandle<()>>,
     status: Arc<Mutex<Status>>,
+    shutdown: watch::Sender<bool>,
 }
 
 impl Server {
     pub fn new(status: Arc<Mutex<Status>>) -> Self {
         Server {
-            handle: None,
+            handles: Vec::new(),
+            listeners: Vec::new(),
             status,
+            shutdown: watch::Sender::new(false),
         }
     }
 
@@ -36,21 +78,129 @@ impl Server {
         }
 
         let status = self.status.clone();
+        self.shutdown.send_replace(false);
 
-        let app = create_router(status.clone());
+        if CONFIG.server.port.is_none() && !CONFIG.server.has_admin_listener() {
+            error!("Neither a port nor a Unix socket is configured for the server.");
+            return Err(());
+        }
 
-        let addr = format!("{}:{}", CONFIG.server.address, CONFIG.server.port);
-        debug!("Connecting to {}", addr);
-        let listener = match TcpListener::bind(&addr).await {
-            Ok(listener) => listener,
+        let tokens = match Tokens::load(&CONFIG.auth) {
+            Ok(tokens) => Arc::new(tokens),
             Err(e) => {
-                error!("Failed to bind to {}: {}", addr, e);
+                error!("{}", e);
+                return Err(());
+            }
+        };
+
+        let policy = match AccessPolicy::new(&CONFIG.access) {
+            Ok(policy) => Arc::new(policy),
+            Err(e) => {
+                error!("{}", e);
                 return Err(());
             }
         };
-        self.handle = Some(tokio::spawn(async move {
-            axum::serve(listener, app).await.unwrap();
-        }));
+
+        let tls = if CONFIG.server.is_tls() {
+            match tls::server_config(&CONFIG.tls) {
+                Ok(config) => Some(RustlsConfig::from_config(Arc::new(config))),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(());
+                }
+            }
+        } else {
+            None
+        };
+
+        // When the status can be changed through an admin listener, the server port only serves
+        // the endpoints that read it, so it can be exposed to the load balancer.
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            tokens.clone(),
+            policy.clone(),
+            self.shutdown.subscribe(),
+        );
+        if let Some(port) = CONFIG.server.port {
+            let access = if CONFIG.server.has_admin_listener() {
+                Access::ReadOnly
+            } else {
+                Access::ReadWrite
+            };
+            let public_app = create_router(
+                status.clone(),
+                access,
+                tokens,
+                policy.clone(),
+                self.shutdown.subscribe(),
+            );
+            let listener = self.bind(&CONFIG.server.address, port).await?;
+            self.serve(listener, public_app, tls.as_ref());
+        }
+
+        if let Some(port) = CONFIG.server.admin_port {
+            let listener = self.bind(&CONFIG.server.admin_address, port).await?;
+            self.serve(listener, app.clone(), tls.as_ref());
+        }
+
+        // Serve the API on a Unix socket, so access can be limited with file permissions.
+        if let Some(path) = &CONFIG.server.socket {
+            let listener = match bind_unix(
+                path,
+                CONFIG.server.socket_mode,
+                CONFIG.server.socket_owner.as_deref(),
+            ) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Failed to listen on {}: {}", path, e);
+                    self.stop().await;
+                    return Err(());
+                }
+            };
+            let shutdown = self.shutdown_signal();
+            let path = path.clone();
+            self.handles.push(tokio::spawn(async move {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+                    .unwrap();
+                let _ = std::fs::remove_file(path);
+            }));
+        }
+
+        // Serve the gRPC health service on a separate port if one is configured.
+        if let Some(grpc_port) = CONFIG.server.grpc_port {
+            let grpc_app = grpc::create_router(status.clone()).layer(
+                middleware::from_fn_with_state(policy.clone(), access::restrict),
+            );
+            let listener = self.bind(&CONFIG.server.address, grpc_port).await?;
+            self.serve(listener, grpc_app, tls.as_ref());
+        }
+
+        // Forget the clients of the rate limiter that have been idle for a while.
+        self.listeners.push(tokio::spawn(access::prune(policy)));
+
+        // Pick up renewed certificates.
+        if let Some(tls) = tls {
+            self.listeners
+                .push(tokio::spawn(tls::watch(tls, &CONFIG.tls)));
+        }
+
+        // Start the HAProxy agent-check listener if a port is configured.
+        if let Some(agent_port) = CONFIG.server.agent_port {
+            let listener = self.bind(&CONFIG.server.address, agent_port).await?;
+            self.listeners
+                .push(tokio::spawn(agent_check::serve(listener, status.clone())));
+        }
+
+        // Start the Zabbix agent listener if a port is configured.
+        if let Some(zabbix_port) = CONFIG.server.zabbix_port {
+            let listener = self.bind(&CONFIG.server.address, zabbix_port).await?;
+            self.listeners
+                .push(tokio::spawn(zabbix::serve(listener, status.clone())));
+        }
+
         info!("Server started.");
         Ok(())
     }
@@ -60,131 +210,654 @@ impl Server {
             return;
         }
 
-        if let Some(handle) = self.handle.take() {
+        // Stop accepting new connections, and give the in-flight requests the chance to complete.
+        self.shutdown.send_replace(true);
+        for handle in self.listeners.drain(..) {
             handle.abort();
         }
+        for mut handle in self.handles.drain(..) {
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut handle)
+                .await
+                .is_err()
+            {
+                warn!("In-flight requests did not complete in time, aborting them.");
+                handle.abort();
+            }
+        }
         info!("Server stopped.");
     }
 
+    /// Serves the app on the listener, over TLS if it is configured.
+    fn serve(&mut self, listener: TcpListener, app: Router, tls: Option<&RustlsConfig>) {
+        let shutdown = self.shutdown_signal();
+        let handle = match tls {
+            Some(tls) => tokio::spawn(serve_tls(listener, app, tls.clone(), shutdown)),
+            None => tokio::spawn(async move {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown)
+                .await
+                .unwrap();
+            }),
+        };
+        self.handles.push(handle);
+    }
+
+    /// Returns a future that completes when the server is asked to shut down.
+    fn shutdown_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut shutdown = self.shutdown.subscribe();
+        async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        }
+    }
+
     async fn is_running(&self) -> bool {
-        if self.handle.is_some() {
+        if !self.handles.is_empty() || !self.listeners.is_empty() {
             return true;
         }
 
         client::is_running().await
     }
+
+    /// Binds a TCP listener to the given address and port. If this fails, the listeners that were
+    /// already started are stopped again.
+    async fn bind(&mut self, address: &str, port: u16) -> Result<TcpListener, ()> {
+        let addr = format!("{}:{}", address, port);
+        debug!("Connecting to {}", addr);
+        match TcpListener::bind(&addr).await {
+            Ok(listener) => Ok(listener),
+            Err(e) => {
+                error!("Failed to bind to {}: {}", addr, e);
+                self.stop().await;
+                Err(())
+            }
+        }
+    }
+}
+
+/// Serves the app over TLS until the shutdown future completes, then lets the in-flight requests
+/// complete.
+async fn serve_tls(
+    listener: TcpListener,
+    app: Router,
+    config: RustlsConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let handle = Handle::new();
+    let graceful = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        graceful.graceful_shutdown(None);
+    });
+    let result = match listener.into_std() {
+        Ok(listener) => {
+            axum_server::from_tcp_rustls(listener, config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("Failed to serve https: {}", e);
+    }
+}
+
+/// Listens on a Unix socket at the given path, with the given file mode and owner. A socket that
+/// was left behind by a previous run is replaced.
+///
+/// The socket is created in a private directory next to the path, and only moved into place once
+/// it has its mode and owner, so there is no moment where clients can connect to it that the mode
+/// doesn't allow.
+fn bind_unix(path: &str, mode: u32, owner: Option<&str>) -> io::Result<UnixListener> {
+    debug!("Listening on {}", path);
+    let private = format!("{}.{}.tmp", path, std::process::id());
+    match std::fs::remove_dir_all(&private) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let result = bind_private(&private, path, mode, owner);
+    let _ = std::fs::remove_dir_all(&private);
+    result
+}
+
+/// Binds the socket in the private directory, and moves it to the path.
+fn bind_private(
+    private: &str,
+    path: &str,
+    mode: u32,
+    owner: Option<&str>,
+) -> io::Result<UnixListener> {
+    let socket = format!("{}/socket", private);
+    let listener = UnixListener::bind(&socket)?;
+    std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(mode))?;
+    if let Some(owner) = owner {
+        let (uid, gid) = parse_owner(owner)?;
+        std::os::unix::fs::chown(&socket, uid, gid)?;
+    }
+    // Renaming replaces a socket that was left behind by a previous run.
+    std::fs::rename(&socket, path)?;
+    Ok(listener)
+}
+
+/// Resolves an owner in the form `user`, `user:group` or `:group` to a user and group ID. Numeric
+/// IDs are accepted too.
+fn parse_owner(owner: &str) -> io::Result<(Option<u32>, Option<u32>)> {
+    let not_found = |kind, name: &str| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The {} {} does not exist.", kind, name),
+        )
+    };
+    let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+    let uid = match user {
+        "" => None,
+        user => match user.parse() {
+            Ok(uid) => Some(uid),
+            Err(_) => User::from_name(user)?
+                .map(|user| user.uid.as_raw())
+                .ok_or_else(|| not_found("user", user))
+                .map(Some)?,
+        },
+    };
+    let gid = match group {
+        "" => None,
+        group => match group.parse() {
+            Ok(gid) => Some(gid),
+            Err(_) => Group::from_name(group)?
+                .map(|group| group.gid.as_raw())
+                .ok_or_else(|| not_found("group", group))
+                .map(Some)?,
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Which endpoints a listener serves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    /// Only the endpoints that read the status.
+    ReadOnly,
+    /// All endpoints, including the ones that change the status.
+    ReadWrite,
 }
 
 /// Create a new router with the given status. Having this in a separate function allows us to use
 /// the router also in tests.
-fn create_router(app_status: Arc<Mutex<Status>>) -> Router {
+fn create_router(
+    app_status: Arc<Mutex<Status>>,
+    access: Access,
+    tokens: Arc<Tokens>,
+    policy: Arc<AccessPolicy>,
+    shutdown: watch::Receiver<bool>,
+) -> Router {
+    let mut router = Router::new();
+    for (path, route) in routes(app_status.clone(), access, shutdown) {
+        router = router.route(path, route);
+    }
+
+    // Only the REST API requires a token. The gRPC health service is a probe.
+    router = router.layer(middleware::from_fn_with_state(tokens, auth::authenticate));
+
+    // Unless it has a port of its own, the gRPC health service is served alongside the REST API.
+    // Its router answers unknown paths with a gRPC "unimplemented" response, which has the 200
+    // status code, so it would report unknown REST paths as healthy. Those get a 404 instead.
+    if CONFIG.server.grpc_port.is_none() {
+        let grpc_router =
+            grpc::create_router(app_status.clone()).fallback(|| async { StatusCode::NOT_FOUND });
+        router = router.merge(grpc_router);
+    }
+
+    // Clients that are not allowed in or make too many requests are turned away from both, before
+    // their token is checked.
+    router
+        .layer(middleware::from_fn_with_state(policy, access::restrict))
+        .layer(TraceLayer::new_for_http())
+}
+
+/// Returns the paths of the REST API that a listener with the given access serves, with their
+/// handlers. The OpenAPI document describes each of them.
+fn routes(
+    app_status: Arc<Mutex<Status>>,
+    access: Access,
+    shutdown: watch::Receiver<bool>,
+) -> Vec<(&'static str, MethodRouter)> {
     let status_get = app_status.clone();
+    let status_events = app_status.clone();
+    let status_wait = app_status.clone();
+    let status_livez = app_status.clone();
+    let status_readyz = app_status.clone();
+    let status_startupz = app_status.clone();
+    let status_deployments = app_status.clone();
+    let status_audit = app_status.clone();
     let status_patch = app_status.clone();
 
-    Router::new()
-        .route("/status", get(move || status(status_get)))
-        .route(
+    let mut routes = vec![
+        (
             "/status",
-            patch(move |Json(payload)| patch_status(status_patch, payload)),
-        )
-        .route("/info", get(info))
-        .layer(TraceLayer::new_for_http())
+            get(
+                move |Query(query): Query<StatusQuery>, headers: HeaderMap| {
+                    status(status_get, query, headers)
+                },
+            ),
+        ),
+        ("/status/events", {
+            let shutdown = shutdown.clone();
+            get(move || events(status_events, shutdown))
+        }),
+        (
+            "/status/wait",
+            get(move |Query(query): Query<WaitQuery>| wait::wait(status_wait, query, shutdown)),
+        ),
+        (
+            "/livez",
+            get(
+                move |Query(query): Query<ProbeQuery>, extensions: Extensions| {
+                    let details = extensions.get::<Anonymous>().is_none();
+                    probes::probe(Probe::Liveness, status_livez, query, details)
+                },
+            ),
+        ),
+        (
+            "/readyz",
+            get(
+                move |Query(query): Query<ProbeQuery>, extensions: Extensions| {
+                    let details = extensions.get::<Anonymous>().is_none();
+                    probes::probe(Probe::Readiness, status_readyz, query, details)
+                },
+            ),
+        ),
+        (
+            "/startupz",
+            get(
+                move |Query(query): Query<ProbeQuery>, extensions: Extensions| {
+                    let details = extensions.get::<Anonymous>().is_none();
+                    probes::probe(Probe::Startup, status_startupz, query, details)
+                },
+            ),
+        ),
+        ("/deployments", get(move || deployments(status_deployments))),
+        ("/schema", get(|| async { Json(schema::schema()) })),
+        ("/openapi.json", get(|| async { Json(openapi::openapi()) })),
+        ("/info", get(info)),
+        ("/", get(dashboard::dashboard)),
+        ("/dashboard", get(dashboard::dashboard)),
+    ];
+
+    // Only read-write listeners change the status. They also serve the audit log, which tells who
+    // changed it, with their address and token, so it is not exposed on the public port.
+    if access == Access::ReadWrite {
+        routes.push((
+            "/status",
+            patch(
+                move |headers: HeaderMap,
+                      extensions: Extensions,
+                      payload: Result<Json<Value>, JsonRejection>| {
+                    let source = request_source(&headers, &extensions);
+                    let if_match = headers.get(header::IF_MATCH).cloned();
+                    let request_id = request_id(&headers);
+                    patch_status(status_patch, payload, source, request_id, if_match)
+                },
+            ),
+        ));
+        routes.push(("/audit", get(move || audit(status_audit))));
+    }
+    routes
+}
+
+/// Returns the current health status of the monitored application, as JSON, as plain text or as
+/// the output of a Nagios plugin, depending on the `format` query parameter or the Accept header.
+/// Browsers get the dashboard. The status code is the same for every format.
+async fn status(status: Arc<Mutex<Status>>, query: StatusQuery, headers: HeaderMap) -> Response {
+    let format = match Format::negotiate(&query, &headers) {
+        Ok(format) => format,
+        Err(problem) => return problem.into_response(),
+    };
+    let mut response = match format {
+        Format::Html => {
+            let status_code = status.lock().await.status_code();
+            let mut response = dashboard::dashboard().await;
+            *response.status_mut() = status_code;
+            response
+        }
+        Format::Json => json_status(&*status.lock().await, &headers),
+        Format::Text | Format::Nagios => {
+            let status = status.lock().await;
+            let body = if format == Format::Text {
+                format::text(&status, query.verbose)
+            } else {
+                format::nagios(&status, query.verbose)
+            };
+            let content_type = [(header::CONTENT_TYPE, "text/plain; charset=utf-8")];
+            (status.status_code(), content_type, body).into_response()
+        }
+    };
+    // Caches need to keep the formats apart.
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
 }
 
-/// Returns the current health status of the monitored application.
-async fn status(status: Arc<Mutex<Status>>) -> Response {
-    use serde_json::to_string;
+/// Returns the status as JSON. Pollers that send the ETag of the status they have get a 304 Not
+/// Modified response as long as it doesn't change.
+fn json_status(status: &Status, headers: &HeaderMap) -> Response {
+    let etag = status.etag();
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(if_none_match, &etag) {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+    }
+    let body = serde_json::to_string(status).unwrap_or_default();
+    let headers = [
+        (header::CONTENT_TYPE, "application/json".to_string()),
+        (header::ETAG, etag),
+    ];
+    (status.status_code(), headers, body).into_response()
+}
 
-    let status = status.lock().await;
-    let response = to_string(&*status).unwrap_or_else(|_| "".to_string());
-    let status_code: StatusCode = status.state.into();
-    (status_code, response).into_response()
+/// Returns whether an If-Match or If-None-Match header matches the entity tag. Both use the weak
+/// comparison: the tag identifies the revision of the status, and the status has no other
+/// representation that could be compared byte for byte.
+fn etag_matches(header: &HeaderValue, etag: &str) -> bool {
+    let Ok(header) = header.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || opaque(tag) == opaque(etag))
 }
 
-/// Patches the status of the monitored application.
-async fn patch_status(status: Arc<Mutex<Status>>, payload: Value) -> Response {
-    debug!("Receive PATCH request for status: {:?}", payload);
-    // Check if the payload is valid for a Status struct, otherwise return a 400 error.
-    // Since this is a PATCH request, the payload might be a partial struct.
-    // Todo: Check if the payload contains any keys that are not in the Status struct.
-    // Todo: Implement setting the deployment_state.
+/// Streams the status as server-sent events: the current status, and the status after every
+/// change. The ID of each event is the revision of the status.
+async fn events(
+    status: Arc<Mutex<Status>>,
+    shutdown: watch::Receiver<bool>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // End the stream when the server shuts down, so it doesn't hold up the shutdown.
+    let changes = WatchStream::new(status.lock().await.subscribe()).map(Some);
+    let stopping = WatchStream::new(shutdown)
+        .filter(|stop| *stop)
+        .map(|_| None);
+    let stream = changes
+        .merge(stopping)
+        .take_while(Option::is_some)
+        .then(move |_| {
+            let status = status.clone();
+            async move {
+                let status = status.lock().await;
+                let event = Event::default()
+                    .event("status")
+                    .id(status.revision.to_string())
+                    .json_data(&*status)
+                    .unwrap_or_default();
+                (status.revision, event)
+            }
+        })
+        // Subscribers are also notified of changes outside of the status, which don't need an
+        // event.
+        .filter_map({
+            let mut last = None;
+            move |(revision, event)| {
+                (last != Some(revision)).then(|| {
+                    last = Some(revision);
+                    Ok(event)
+                })
+            }
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
 
-    let mut status = status.lock().await;
+/// Returns who made a request, for the audit log. Requests made with the CLI are told apart by
+/// their user agent.
+fn request_source(headers: &HeaderMap, extensions: &Extensions) -> Source {
+    let client = extensions
+        .get::<ClientAddr>()
+        .map(|ClientAddr(client)| *client);
+    let token = extensions.get::<Token>().map(|token| token.id.clone());
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    if user_agent == Some(client::USER_AGENT) {
+        Source::Cli { client, token }
+    } else {
+        Source::Api { client, token }
+    }
+}
+
+/// Returns the ID of a request: the one in the X-Request-Id header, which is set by some proxies
+/// and can be set by the client, or a new one.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// The fields that can be sent to `PATCH /status`.
+pub const PATCH_FIELDS: [&str; 4] = ["health", "message", "phase", "deployment"];
+
+/// Validates the body of a PATCH request. Unknown fields are rejected, so a typo doesn't go
+/// unnoticed.
+fn parse_patch(payload: &Value) -> Result<StatusPatch, Problem> {
+    let invalid =
+        |field: &str, detail: String| Problem::field(StatusCode::BAD_REQUEST, field, detail);
+    let Some(fields) = payload.as_object() else {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "The request body should be a JSON object.",
+        ));
+    };
+    if let Some(field) = fields
+        .keys()
+        .find(|key| !PATCH_FIELDS.contains(&key.as_str()))
+    {
+        return Err(invalid(field, format!("Unknown field {}.", field)));
+    }
 
-    // If the 'health' key is present, check if the value is valid before updating the status.
+    let mut patch = StatusPatch::default();
     if let Some(health) = payload.get("health") {
-        if let Some(health_str) = health.as_str() {
-            match HealthState::try_from(health_str) {
-                Ok(health_state) => {
-                    debug!("Setting health state to: {}", health_state);
-                    status.state = health_state
-                }
-                Err(err) => {
-                    debug!("Invalid health state: {}", err);
-                    return (StatusCode::BAD_REQUEST, err).into_response();
-                }
-            }
-        } else {
-            debug!("Invalid health state: {:?}", health);
-            return (StatusCode::BAD_REQUEST, "Invalid health state.").into_response();
+        let health = health.as_str().ok_or("Invalid health state.");
+        patch.health = Some(
+            health
+                .and_then(HealthState::try_from)
+                .map_err(|err| invalid("health", err.to_string()))?,
+        );
+    }
+    if let Some(message) = payload.get("message") {
+        let message = message
+            .as_str()
+            .ok_or_else(|| invalid("message", "The message should be a string.".to_string()))?;
+        patch.message = Some(message.to_string());
+    }
+    if let Some(phase) = payload.get("phase") {
+        let phase = phase.as_str().ok_or("Invalid deployment phase.");
+        patch.phase = Some(
+            phase
+                .and_then(DeploymentPhase::try_from)
+                .map_err(|err| invalid("phase", err.to_string()))?,
+        );
+    }
+    if let Some(deployment) = payload.get("deployment") {
+        let update =
+            serde_json::from_value::<DeploymentUpdate>(deployment.clone()).map_err(|err| {
+                invalid(
+                    "deployment",
+                    format!("Invalid deployment metadata: {}", err),
+                )
+            })?;
+        if update.progress.is_some_and(|progress| progress > 100) {
+            return Err(invalid(
+                "deployment.progress",
+                "The deployment progress should be a percentage.".to_string(),
+            ));
         }
+        patch.deployment = Some(update);
     }
+    Ok(patch)
+}
 
-    // If the 'message' key is present, append the message.
-    if let Some(message) = payload.get("message") {
-        if let Some(message_str) = message.as_str() {
-            debug!("Appending message: {}", message_str);
-            status.add_message(message_str.to_string());
-        } else {
-            debug!("Invalid message: {:?}", message);
-            return (StatusCode::BAD_REQUEST, "Invalid message.").into_response();
+/// Patches the status of the monitored application. The whole request is validated before the
+/// status is changed, so an invalid request doesn't change anything.
+async fn patch_status(
+    status: Arc<Mutex<Status>>,
+    payload: Result<Json<Value>, JsonRejection>,
+    source: Source,
+    request_id: String,
+    if_match: Option<HeaderValue>,
+) -> Response {
+    let payload = match payload {
+        Ok(Json(payload)) => payload,
+        Err(rejection) => {
+            debug!("Invalid PATCH request: {}", rejection.body_text());
+            return Problem::new(rejection.status(), rejection.body_text()).into_response();
+        }
+    };
+    debug!("Receive PATCH request for status: {:?}", payload);
+    let mut patch = match parse_patch(&payload) {
+        Ok(patch) => patch,
+        Err(problem) => {
+            debug!("Invalid PATCH request: {}", problem.detail);
+            return problem.into_response();
+        }
+    };
+
+    let mut status = status.lock().await;
+    // Refuse the change if the status changed since the client read it, so concurrent updates
+    // don't silently overwrite each other.
+    if let Some(if_match) = if_match {
+        let etag = status.etag();
+        if !etag_matches(&if_match, &etag) {
+            debug!("The status changed since {:?}", if_match);
+            let message = format!(
+                "The status has changed, its current revision is {}.",
+                status.revision
+            );
+            let problem = Problem::new(StatusCode::PRECONDITION_FAILED, message);
+            return ([(header::ETAG, etag)], problem).into_response();
+        }
+    }
+    if let Some(phase) = &patch.phase {
+        if !status.phase.can_transition_to(phase) {
+            debug!(
+                "Invalid deployment phase transition: {} to {}",
+                status.phase, phase
+            );
+            let message = format!(
+                "Cannot change the deployment phase from {} to {}.",
+                status.phase, phase
+            );
+            return Problem::field(StatusCode::CONFLICT, "phase", message).into_response();
         }
     }
 
-    // Set the deployment phase if the 'phase' key is present.
-    if let Some(phase) = payload.get("phase") {
-        if let Some(phase_str) = phase.as_str() {
-            match DeploymentPhase::try_from(phase_str) {
-                Ok(phase) => {
-                    debug!("Setting deployment phase to: {}", phase);
-                    status.phase = phase;
-                }
-                Err(err) => {
-                    debug!("Invalid deployment phase: {}", err);
-                    return (StatusCode::BAD_REQUEST, err).into_response();
-                }
+    let before = status.snapshot();
+    if let Some(health_state) = patch.health {
+        debug!("Setting health state to: {}", health_state);
+        status.set_state(health_state);
+    }
+    if let Some(message) = patch.message {
+        debug!("Appending message: {}", message);
+        status.add_message(message);
+    }
+    if let Some(phase) = patch.phase {
+        // Metadata that is sent when a deployment ends describes the deployment that ends, so
+        // apply it before changing the phase.
+        if status.deployment.is_some() {
+            if let Some(update) = patch.deployment.take() {
+                status.update_deployment(update);
             }
-        } else {
-            debug!("Invalid deployment phase: {:?}", phase);
-            return (StatusCode::BAD_REQUEST, "Invalid deployment phase.").into_response();
         }
+        debug!("Setting deployment phase to: {}", phase);
+        status.set_phase(phase);
+    }
+    if let Some(update) = patch.deployment {
+        debug!("Updating deployment metadata: {:?}", update);
+        status.update_deployment(update);
     }
 
-    (StatusCode::OK, "Status updated.").into_response()
+    status.audit(before, source, Some(request_id.clone()));
+    (
+        StatusCode::OK,
+        [
+            (X_REQUEST_ID.clone(), request_id),
+            (header::ETAG, status.etag()),
+        ],
+        "Status updated.",
+    )
+        .into_response()
+}
+
+/// Returns the recent changes to the state and the phase, oldest first.
+async fn audit(status: Arc<Mutex<Status>>) -> Json<Vec<AuditRecord>> {
+    Json(status.lock().await.audit.records())
+}
+
+/// Returns the running release, the deployment in progress and the past deployments.
+async fn deployments(status: Arc<Mutex<Status>>) -> Json<crate::status::Deployments> {
+    Json(status.lock().await.deployments())
+}
+
+/// The name and version of the health monitor, as served on `/info`. The CLI uses it to find out
+/// whether our server is running.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Info {
+    pub name: String,
+    pub version: String,
+}
+
+impl Info {
+    pub fn current() -> Self {
+        Info {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
 }
 
 /// Returns the application name and version.
-async fn info() -> String {
-    let name = env!("CARGO_PKG_NAME");
-    let version = env!("CARGO_PKG_VERSION");
-    format!("{{\"name\": \"{}\", \"version\": \"{}\"}}", name, version)
+async fn info() -> Json<Info> {
+    Json(Info::current())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{AccessConfig, AuthConfig};
+    use crate::wait::Condition;
     use axum::body::Body;
+    use axum::extract::ConnectInfo;
     use axum::http::{Request, Response, StatusCode};
     use axum::Router;
     use http_body_util::BodyExt;
     use serde_json::json;
+    use std::collections::BTreeSet;
     use std::sync::Arc;
     use tokio::sync::Mutex;
+    use tokio::time::timeout;
     use tower::ServiceExt;
 
+    /// Returns the shutdown signal of a server that is never stopped.
+    fn running() -> watch::Receiver<bool> {
+        watch::Sender::new(false).subscribe()
+    }
+
     async fn setup() -> Router {
         let status = Arc::new(Mutex::new(Status::new()));
-        create_router(status.clone())
+        create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        )
     }
 
     #[tokio::test]
@@ -202,7 +875,11 @@ mod tests {
             .unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
-        let expected = r#"{"state":"healthy","messages":[],"phase":"online"}"#;
+        assert!(response.headers()["etag"]
+            .to_str()
+            .unwrap()
+            .ends_with("-0\""));
+        let expected = r#"{"state":"healthy","messages":[],"phase":"online","revision":0}"#;
         let body = response.into_body().collect().await.unwrap().to_bytes();
         assert_eq!(&body[..], expected.as_bytes());
     }
@@ -210,7 +887,13 @@ mod tests {
     #[tokio::test]
     async fn test_status_unhealthy() {
         let status = Arc::new(Mutex::new(Status::new()));
-        let app = create_router(status.clone());
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
 
         status.lock().await.state = HealthState::Unhealthy;
 
@@ -228,89 +911,1254 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_patch_status() {
+    async fn test_conditional_requests() {
         let status = Arc::new(Mutex::new(Status::new()));
-        let app = create_router(status.clone());
-        assert_eq!(status.lock().await.state, HealthState::Healthy);
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        let request = |method: &str, header: Option<(&str, &str)>, body: &str| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri("/status")
+                .header("content-type", "application/json");
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            app.clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        };
 
-        let payload = json!({ "health": "unhealthy" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(status.lock().await.state, HealthState::Unhealthy);
+        // Pollers get a 304 response while the status doesn't change.
+        let response = request("GET", None, "").await.unwrap();
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let response = request("GET", Some(("if-none-match", &etag)), "")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        let strong = etag.trim_start_matches("W/").to_string();
+        let response = request("GET", Some(("if-none-match", &strong)), "")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
 
-        let payload = json!({ "health": "healthy" });
-        let response = get_patch_response(&app, payload).await;
+        // A change that is based on the current revision is made, and returns the new ETag.
+        let body = r#"{"health": "unhealthy"}"#;
+        let response = request("PATCH", Some(("if-match", &etag)), body)
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(status.lock().await.state, HealthState::Healthy);
+        let new_etag = response.headers()["etag"].to_str().unwrap().to_string();
+        assert_ne!(new_etag, etag);
+        assert_eq!(new_etag, status.lock().await.etag());
 
-        let payload = json!({ "health": "invalid" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-        assert_eq!(status.lock().await.state, HealthState::Healthy);
+        // A change that is based on an old revision is refused.
+        let body = r#"{"health": "healthy"}"#;
+        let response = request("PATCH", Some(("if-match", &etag)), body)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers()["etag"], new_etag.as_str());
+        assert_eq!(status.lock().await.state, HealthState::Unhealthy);
+        let response = request("PATCH", Some(("if-match", &strong)), body)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
 
-        let payload = json!({ "message": "Test message" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(status.lock().await.messages.len(), 1);
-        assert!(status
-            .lock()
+        let response = request("GET", Some(("if-none-match", &etag)), "")
             .await
-            .messages
-            .contains(&"Test message".to_string()));
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = request("PATCH", Some(("if-match", "*")), body)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn test_patch_phase() {
+    async fn test_events() {
         let status = Arc::new(Mutex::new(Status::new()));
-        let app = create_router(status.clone());
-        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
-
-        let payload = json!({ "phase": "deploying" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(status.lock().await.phase, DeploymentPhase::Deploying);
-
-        let payload = json!({ "phase": "online" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
-
-        let payload = json!({ "phase": "invalid" });
-        let response = get_patch_response(&app, payload).await;
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
-    }
-
-    async fn get_patch_response(app: &Router, payload: Value) -> Response<Body> {
-        app.clone()
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        let response = app
             .oneshot(
                 Request::builder()
-                    .method("PATCH")
-                    .uri("/status")
-                    .header("content-type", "application/json")
-                    .body(Body::from(payload.to_string()))
+                    .uri("/status/events")
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
-            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body();
+
+        // The current status is sent first, then the status after every change.
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let event = String::from_utf8_lossy(&frame).to_string();
+        assert!(event.starts_with("event: status\n"));
+        assert!(event.contains("id: 0\n"));
+        assert!(event.contains(r#""revision":0"#));
+
+        // Changes outside of the status don't send an event.
+        status.lock().await.complete_startup();
+        assert!(timeout(Duration::from_millis(50), body.frame())
+            .await
+            .is_err());
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let event = String::from_utf8_lossy(&frame).to_string();
+        assert!(event.contains("id: 1\n"));
+        assert!(event.contains(r#""state":"unhealthy""#));
     }
 
     #[tokio::test]
-    async fn test_info() {
-        let app = setup().await;
+    async fn test_status_formats() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        {
+            let mut status = status.lock().await;
+            status.set_phase(DeploymentPhase::Online);
+            status.set_check_result("UrlCheck", &Err("Timeout".to_string()));
+            status.set_state(HealthState::Unhealthy);
+            status.add_message("UrlCheck: Timeout".to_string());
+        }
+        let request = |method: &str, uri: &str, accept: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("accept", accept)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+        let body = |response: Response<Body>| async move {
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
 
-        let response = app
-            .oneshot(Request::builder().uri("/info").body(Body::empty()).unwrap())
+        let response = request("GET", "/status", "application/json").await.unwrap();
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.headers()["vary"], "accept");
+
+        // Plain text is the one-line form, for scripts that grep the response.
+        let response = request("GET", "/status", "text/plain").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(body(response).await, "unhealthy: UrlCheck: Timeout\n");
+        let response = request("GET", "/status?verbose=true", "text/plain")
+            .await
+            .unwrap();
+        assert_eq!(
+            body(response).await,
+            "[-]UrlCheck failed: Timeout\nunhealthy: UrlCheck: Timeout\n"
+        );
+
+        // The format parameter wins over the Accept header.
+        let response = request("GET", "/status?format=nagios", "application/json")
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body(response)
+            .await
+            .starts_with("CRITICAL - unhealthy, online: UrlCheck: Timeout | checks=1;;;0"));
+        let response = request("GET", "/status?format=xml", "*/*").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem: Problem = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(problem.field.as_deref(), Some("format"));
 
+        // HEAD only returns the status code.
+        let response = request("HEAD", "/status", "*/*").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(response).await, "");
+        status.lock().await.set_state(HealthState::Healthy);
+        let response = request("HEAD", "/status", "*/*").await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_dashboard() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        let request = |uri: &str, accept: &str| {
+            let request = Request::builder()
+                .uri(uri)
+                .header("accept", accept)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for uri in ["/", "/dashboard"] {
+            let response = request(uri, "*/*").await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()["content-type"],
+                "text/html; charset=utf-8"
+            );
+            assert!(response.headers().contains_key("content-security-policy"));
+        }
 
+        // Browsers that open the status get the dashboard, other clients get JSON.
+        let response = request("/status", "text/html,*/*;q=0.8").await.unwrap();
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.status(), StatusCode::OK);
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        let expected = format!(
-            "{{\"name\": \"healthmonitor\", \"version\": \"{}\"}}",
-            env!("CARGO_PKG_VERSION")
+        assert!(String::from_utf8_lossy(&body).contains("<title>HealthMonitor</title>"));
+        let response = request("/status", "application/json").await.unwrap();
+        assert!(response.headers()["etag"]
+            .to_str()
+            .unwrap()
+            .ends_with("-0\""));
+
+        // The status code of the dashboard is the one of the status, as for the other formats.
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let response = request("/status", "text/html").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
         );
-        assert_eq!(&body[..], expected.as_bytes());
+        let response = request("/dashboard", "text/html").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        let wait = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+
+        // A condition that is already met returns right away.
+        let response = wait("/status/wait?state=healthy&phase=online").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["etag"]
+            .to_str()
+            .unwrap()
+            .ends_with("-0\""));
+
+        // Otherwise the request is held until the status changes.
+        let waiting = tokio::spawn(wait("/status/wait?state=unhealthy&timeout=10s"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let response = waiting.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["state"], "unhealthy");
+
+        // Changes that don't meet the condition keep it waiting until the timeout expires.
+        let waiting = tokio::spawn(wait("/status/wait?phase=maintenance&timeout=1s"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        status.lock().await.set_state(HealthState::Healthy);
+        let response = waiting.await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let problem: Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem.detail,
+            "The status did not become maintenance within 1s."
+        );
+
+        // Invalid parameters are reported.
+        for (uri, field) in [
+            ("/status/wait?state=fine", "state"),
+            ("/status/wait?phase=offline", "phase"),
+            ("/status/wait?timeout=soon", "timeout"),
+            ("/status/wait?timeout=2h", "timeout"),
+        ] {
+            let response = wait(uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let problem: Problem = serde_json::from_slice(&body).unwrap();
+            assert_eq!(problem.field.as_deref(), Some(field), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (shutdown, receiver) = watch::channel(false);
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            receiver,
+        );
+        let get = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            }
+        };
+        let waiting = tokio::spawn(get("/status/wait?phase=maintenance&timeout=1h"));
+        let mut events = get("/status/events").await.into_body();
+        events.frame().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        // Pending requests end when the server shuts down.
+        shutdown.send_replace(true);
+        let response = timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(timeout(Duration::from_secs(1), events.frame())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_patch_status() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        let payload = json!({ "health": "unhealthy" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.state, HealthState::Unhealthy);
+
+        let payload = json!({ "health": "healthy" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        let payload = json!({ "health": "invalid" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        let payload = json!({ "message": "Test message" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.messages.len(), 1);
+        assert!(status
+            .lock()
+            .await
+            .messages
+            .contains(&"Test message".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_patch_phase() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
+
+        let payload = json!({ "phase": "deploying" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Deploying);
+
+        let payload = json!({ "phase": "online" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
+
+        let payload = json!({ "phase": "invalid" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
+    }
+
+    #[tokio::test]
+    async fn test_patch_phase_draining_and_maintenance() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+
+        let payload = json!({ "phase": "maintenance", "message": "Upgrading the database" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Maintenance);
+        assert_eq!(
+            status.lock().await.status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // An instance in maintenance is already out of rotation, there is nothing to drain.
+        let payload = json!({ "phase": "draining" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Maintenance);
+
+        let payload = json!({ "phase": "online" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let payload = json!({ "phase": "draining" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Draining);
+
+        // A draining instance can not be deployed or put in maintenance.
+        let payload = json!({ "phase": "deploying" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Draining);
+
+        // The drain can be cancelled by putting the instance back online.
+        let payload = json!({ "phase": "online" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.phase, DeploymentPhase::Online);
+    }
+
+    #[tokio::test]
+    async fn test_patch_validation() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+
+        let problem = |response: Response<Body>| async move {
+            assert_eq!(
+                response.headers()["content-type"],
+                "application/problem+json"
+            );
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<Value>(&body).unwrap()
+        };
+
+        // Unknown fields are rejected.
+        let payload = json!({ "health": "unhealthy", "helth": "healthy" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem_details = problem(response).await;
+        assert_eq!(problem_details["field"], "helth");
+        assert_eq!(problem_details["status"], 400);
+        assert_eq!(problem_details["title"], "Bad Request");
+
+        // Nothing is changed when a field is invalid.
+        let payload = json!({ "health": "unhealthy", "message": "Down", "phase": "offline" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem(response).await["field"], "phase");
+        let payload = json!({ "phase": "deploying", "deployment": { "progress": 140 } });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(problem(response).await["field"], "deployment.progress");
+        {
+            let status = status.lock().await;
+            assert_eq!(status.state, HealthState::Healthy);
+            assert_eq!(status.phase, DeploymentPhase::Online);
+            assert!(status.messages.is_empty());
+            assert!(status.audit.records().is_empty());
+        }
+
+        // Nor when the phase can't be changed.
+        status.lock().await.set_phase(DeploymentPhase::Draining);
+        let payload = json!({ "health": "unhealthy", "phase": "deploying" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(problem(response).await["field"], "phase");
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        // The body should be a JSON object.
+        let payload = json!(["unhealthy"]);
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(problem(response).await.get("field").is_none());
+        let request = Request::builder()
+            .method("PATCH")
+            .uri("/status")
+            .header("content-type", "application/json")
+            .body(Body::from("{"))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        problem(response).await;
+    }
+
+    #[tokio::test]
+    async fn test_schema() {
+        let app = setup().await;
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/schema")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let schema: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            schema["$defs"]["StatusPatch"]["additionalProperties"],
+            false
+        );
+        assert!(schema["$defs"]["Status"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_deployments() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+
+        let payload = json!({
+            "phase": "deploying",
+            "deployment": { "version": "1.2.0", "deployer": "jenkins", "step": "database updates", "progress": 40 }
+        });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let payload = json!({ "deployment": { "progress": 140 } });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let payload = json!({ "deployment": { "colour": "blue" } });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/deployments")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let deployments: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(deployments["deployment"]["version"], "1.2.0");
+        assert_eq!(deployments["deployment"]["step"], "database updates");
+        assert_eq!(deployments["deployment"]["progress"], 40);
+        assert_eq!(deployments["history"], json!([]));
+
+        // The metadata sent when going online belongs to the deployment that ends.
+        let payload = json!({ "phase": "online", "deployment": { "sha": "8f3c2a1" } });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let deployments = status.lock().await.deployments();
+        assert_eq!(deployments.release.sha, Some("8f3c2a1".to_string()));
+        assert_eq!(deployments.release.version, Some("1.2.0".to_string()));
+        assert_eq!(deployments.deployment, None);
+        assert_eq!(deployments.history.len(), 1);
+    }
+
+    async fn get_patch_response(app: &Router, payload: Value) -> Response<Body> {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("PATCH")
+                    .uri("/status")
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadOnly,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+
+        let payload = json!({ "health": "unhealthy" });
+        let response = get_patch_response(&app, payload).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+
+        for uri in ["/status", "/livez", "/readyz", "/deployments", "/info"] {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/audit")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let config = AuthConfig {
+            tokens: vec!["haproxy:read:abc".to_string(), "cli:write:def".to_string()],
+            tokens_file: None,
+            token: None,
+        };
+        let tokens = Arc::new(Tokens::load(&config).unwrap());
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            tokens,
+            Arc::default(),
+            running(),
+        );
+
+        let request = |method: &str, uri: &str, token: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            let body = if method == "PATCH" {
+                Body::from(r#"{"health": "unhealthy"}"#)
+            } else {
+                Body::empty()
+            };
+            app.clone().oneshot(request.body(body).unwrap())
+        };
+
+        // Reading needs a valid token with any role.
+        let response = request("GET", "/status", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        let response = request("GET", "/status", Some("xyz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = request("GET", "/status", Some("abc")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Changing the status needs the write role.
+        let response = request("PATCH", "/status", Some("abc")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+        let response = request("PATCH", "/status", Some("def")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(status.lock().await.state, HealthState::Unhealthy);
+
+        // The probes and the dashboard page don't need a token. Without one, the probes don't tell
+        // why they fail.
+        let response = request("GET", "/livez", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request("GET", "/readyz?verbose=true", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "readyz check failed");
+        let response = request("GET", "/readyz?verbose=true", Some("xyz"))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "readyz check failed");
+        let response = request("GET", "/readyz?verbose=true", Some("abc"))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("[-]health failed"));
+        let response = request("GET", "/dashboard", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let config = AuthConfig {
+            tokens: vec!["haproxy:read:abc".to_string()],
+            tokens_file: None,
+            token: None,
+        };
+        let tokens = Arc::new(Tokens::load(&config).unwrap());
+        for tokens in [Arc::default(), tokens] {
+            let app = create_router(
+                Arc::new(Mutex::new(Status::new())),
+                Access::ReadWrite,
+                tokens,
+                Arc::default(),
+                running(),
+            );
+            for token in [None, Some("abc")] {
+                let mut request = Request::builder().uri("/nonexistent");
+                if let Some(token) = token {
+                    request = request.header("authorization", format!("Bearer {}", token));
+                }
+                let response = app
+                    .clone()
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+                assert!(!response.headers().contains_key("grpc-status"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_policy() {
+        let config = AccessConfig {
+            allow_read: vec![],
+            allow_write: vec!["127.0.0.1".to_string()],
+            trusted_proxies: vec![],
+            rate_limit: Some(0.1),
+            rate_limit_burst: 2,
+        };
+        let policy = Arc::new(AccessPolicy::new(&config).unwrap());
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            policy,
+            running(),
+        );
+
+        let request = |method: &str, client: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri("/status")
+                .header("content-type", "application/json");
+            if let Some(client) = client {
+                let addr: SocketAddr = format!("{}:50000", client).parse().unwrap();
+                request = request.extension(ConnectInfo(addr));
+            }
+            let body = if method == "PATCH" {
+                Body::from(r#"{"health": "unhealthy"}"#)
+            } else {
+                Body::empty()
+            };
+            app.clone().oneshot(request.body(body).unwrap())
+        };
+
+        // Only localhost can change the status.
+        let response = request("PATCH", Some("10.0.0.5")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = request("PATCH", Some("127.0.0.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // After the burst a client has to wait.
+        for _ in 0..2 {
+            let response = request("GET", Some("10.0.0.5")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        let response = request("GET", Some("10.0.0.5")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "10");
+
+        // IPv4 clients of a dual-stack listener get the same treatment.
+        let response = request("GET", Some("[::ffff:10.0.0.5]")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = request("PATCH", Some("[::ffff:127.0.0.1]")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Requests over the Unix socket are not limited.
+        let response = request("GET", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Calls of the gRPC health service read the status, and are limited as well.
+        let addr: SocketAddr = "10.0.0.6:50000".parse().unwrap();
+        let grpc_request = || {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/grpc.health.v1.Health/Check")
+                .header("content-type", "application/grpc")
+                .extension(ConnectInfo(addr))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+        for _ in 0..2 {
+            let response = grpc_request().await.unwrap();
+            assert_ne!(response.status(), StatusCode::FORBIDDEN);
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let response = grpc_request().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let config = AuthConfig {
+            tokens: vec!["deploy:write:def".to_string()],
+            tokens_file: None,
+            token: None,
+        };
+        let tokens = Arc::new(Tokens::load(&config).unwrap());
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            tokens,
+            Arc::default(),
+            running(),
+        );
+
+        let addr: SocketAddr = "10.0.0.5:50000".parse().unwrap();
+        let request = Request::builder()
+            .method("PATCH")
+            .uri("/status")
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer def")
+            .header("user-agent", client::USER_AGENT)
+            .header("x-request-id", "abc")
+            .extension(ConnectInfo(addr))
+            .body(Body::from(
+                r#"{"health": "unhealthy", "message": "Disk full", "phase": "maintenance"}"#,
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "abc");
+
+        // Without a request ID one is generated.
+        let request = Request::builder()
+            .method("PATCH")
+            .uri("/status")
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer def")
+            .body(Body::from(r#"{"message": "Still full"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let request_id = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(request_id.len(), 36);
+
+        let request = Request::builder()
+            .uri("/audit")
+            .header("authorization", "Bearer def")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            records[0]["source"],
+            json!({"type": "cli", "client": "10.0.0.5", "token": "deploy"})
+        );
+        assert_eq!(records[0]["request_id"], "abc");
+        assert_eq!(
+            records[0]["state"],
+            json!({"old": "healthy", "new": "unhealthy"})
+        );
+        assert_eq!(
+            records[0]["phase"],
+            json!({"old": "online", "new": "maintenance"})
+        );
+        assert_eq!(records[0]["message"], "Disk full");
+        assert_eq!(
+            records[1]["source"],
+            json!({"type": "api", "token": "deploy"})
+        );
+        assert_eq!(records[1]["request_id"], request_id);
+        assert!(records[1].get("state").is_none());
+        assert_eq!(records[1]["message"], "Still full");
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let certificates = crate::tls::tests::TestCertificates::new();
+        let config = certificates.config(true);
+        let rustls_config =
+            RustlsConfig::from_config(Arc::new(tls::server_config(&config).unwrap()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = create_router(
+            Arc::new(Mutex::new(Status::new())),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        tokio::spawn(serve_tls(
+            listener,
+            app,
+            rustls_config,
+            std::future::pending(),
+        ));
+        let url = format!("https://localhost:{}/status", addr.port());
+
+        // A client with a certificate signed by the CA is let in.
+        let builder = reqwest::Client::builder().resolve("localhost", addr);
+        let client = client::with_tls(builder, &config).unwrap().build().unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without a client certificate the handshake fails.
+        let mut anonymous = certificates.config(true);
+        anonymous.client_cert = None;
+        let builder = reqwest::Client::builder().resolve("localhost", addr);
+        let client = client::with_tls(builder, &anonymous)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(client.get(&url).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let app = setup().await;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let document: Value = serde_json::from_slice(&body).unwrap();
+
+        // Every route is documented.
+        let documented: BTreeSet<&str> = document["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let routes = routes(
+            Arc::new(Mutex::new(Status::new())),
+            Access::ReadWrite,
+            running(),
+        );
+        let served: BTreeSet<&str> = routes.iter().map(|(path, _)| *path).collect();
+        assert_eq!(documented, served);
+
+        // The documented methods are the ones that are served. HEAD is served along with GET.
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            let mut methods: BTreeSet<String> = operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|method| method.to_uppercase())
+                .collect();
+            if methods.contains("GET") {
+                methods.insert("HEAD".to_string());
+            }
+            let request = Request::builder()
+                .method("TRACE")
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                path
+            );
+            let allowed: BTreeSet<String> = response.headers()["allow"]
+                .to_str()
+                .unwrap()
+                .split(',')
+                .map(|method| method.trim().to_string())
+                .collect();
+            assert_eq!(allowed, methods, "{}", path);
+        }
+
+        // Every documented operation is served.
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                let request = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(path)
+                    .header("content-type", "application/json")
+                    .body(Body::from("{}"))
+                    .unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                assert_ne!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+                assert_ne!(
+                    response.status(),
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{}",
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let api = client::ApiClient::with_base_url(
+            reqwest::Client::new(),
+            &format!("http://{}/", addr),
+            None,
+        );
+
+        assert_eq!(api.info().await.unwrap(), Info::current());
+
+        let patch = StatusPatch {
+            health: Some(HealthState::Unhealthy),
+            message: Some("Disk full".to_string()),
+            phase: Some(DeploymentPhase::Deploying),
+            deployment: Some(DeploymentUpdate {
+                version: Some("1.2.0".to_string()),
+                ..DeploymentUpdate::default()
+            }),
+        };
+        api.patch_status(&patch).await.unwrap();
+
+        // The unhealthy status is served with a 503 response.
+        let current = api.status().await.unwrap();
+        assert_eq!(current.state, HealthState::Unhealthy);
+        assert_eq!(current.messages, ["Disk full"]);
+        assert_eq!(current.phase, DeploymentPhase::Deploying);
+        let deployments = api.deployments().await.unwrap();
+        assert_eq!(
+            deployments.deployment.unwrap().release.version.as_deref(),
+            Some("1.2.0")
+        );
+
+        // Waiting returns the status once it meets the condition, and nothing after a timeout.
+        let healthy = Condition {
+            state: Some(HealthState::Healthy),
+            phase: None,
+        };
+        assert!(api.wait(&healthy, Duration::ZERO).await.unwrap().is_none());
+        status.lock().await.set_state(HealthState::Healthy);
+        let current = api.wait(&healthy, Duration::ZERO).await.unwrap().unwrap();
+        assert_eq!(current.state, HealthState::Healthy);
+
+        // Errors carry the description of the problem.
+        status.lock().await.set_phase(DeploymentPhase::Draining);
+        let patch = StatusPatch {
+            phase: Some(DeploymentPhase::Maintenance),
+            ..StatusPatch::default()
+        };
+        match api.patch_status(&patch).await {
+            Err(client::ClientError::ServerError(code, message)) => {
+                assert_eq!(code, StatusCode::CONFLICT);
+                assert_eq!(
+                    message,
+                    "Cannot change the deployment phase from draining to maintenance."
+                );
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+
+        let get = |uri: &'static str| {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(get("/livez").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(get("/readyz").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            get("/startupz").await.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        status.lock().await.complete_startup();
+        status
+            .lock()
+            .await
+            .set_check_result("FileCheck", &Err("File is empty".to_string()));
+        assert_eq!(get("/livez").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            get("/readyz").await.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            get("/readyz?exclude=FileCheck").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            get("/startupz?include=startup").await.unwrap().status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_grpc_health() {
+        use tonic_health::pb::health_check_response::ServingStatus;
+        use tonic_health::pb::health_client::HealthClient;
+        use tonic_health::pb::HealthCheckRequest;
+
+        // gRPC needs HTTP/2, so serve the router on a real socket instead of calling it directly.
+        let status = Arc::new(Mutex::new(Status::new()));
+        let app = create_router(
+            status.clone(),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+        let request = HealthCheckRequest {
+            service: "".to_string(),
+        };
+        let response = client.check(request.clone()).await.unwrap().into_inner();
+        assert_eq!(response.status, ServingStatus::Serving as i32);
+
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let response = client.check(request).await.unwrap().into_inner();
+        assert_eq!(response.status, ServingStatus::NotServing as i32);
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let app = setup().await;
+
+        let response = app
+            .oneshot(Request::builder().uri("/info").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let info: Info = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info.name, "healthmonitor");
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_unix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("healthmonitor.sock");
+        let path = path.to_str().unwrap();
+
+        // A socket left behind by a previous run is replaced.
+        drop(bind_unix(path, 0o600, None).unwrap());
+        let listener = bind_unix(path, 0o660, None).unwrap();
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        // The socket is moved into place from a private directory, which is removed.
+        let entries: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["healthmonitor.sock"]);
+
+        let app = create_router(
+            Arc::new(Mutex::new(Status::new())),
+            Access::ReadWrite,
+            Arc::default(),
+            Arc::default(),
+            running(),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::builder()
+            .unix_socket(path)
+            .build()
+            .unwrap();
+        let response = client.get("http://localhost/status").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(parse_owner("root").unwrap(), (Some(0), None));
+        assert_eq!(parse_owner("1000:1001").unwrap(), (Some(1000), Some(1001)));
+        assert_eq!(parse_owner(":0").unwrap(), (None, Some(0)));
+        assert!(parse_owner("healthmonitor-missing-user").is_err());
     }
 }
@@ -1,27 +1,533 @@
+use crate::audit::{AuditLog, AuditRecord, Change, Source};
 use crate::config::CONFIG;
 use axum::http::StatusCode;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
+use tokio::sync::watch;
 
 #[derive(Deserialize, Serialize)]
 pub struct Status {
     pub state: HealthState,
     pub messages: Vec<String>,
     pub phase: DeploymentPhase,
+    /// The result of the most recent run of each health check, keyed by check name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<String, CheckResult>,
+    /// The deployment that is in progress, if the application is being deployed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<Deployment>,
+    /// The release that was most recently deployed successfully.
+    #[serde(default, skip_serializing_if = "Release::is_empty")]
+    pub release: Release,
+    /// Increases with every change of the state, the phase, the messages, the deployment or the
+    /// outcome of a check, so clients can tell whether the status changed since they last read it.
+    /// Times like the last run of a check and the elapsed time of a deployment change without it,
+    /// which is why the entity tag is weak.
+    #[serde(default)]
+    pub revision: u64,
+    /// Tells the revisions of this run of the monitor apart from those of an earlier run, which
+    /// also started counting at 0.
+    #[serde(skip, default = "boot")]
+    boot: String,
+    /// The most recent finished deployments, oldest first.
+    #[serde(skip)]
+    pub history: VecDeque<DeploymentRecord>,
+    /// The checks that are currently running, with the time they started.
+    #[serde(skip)]
+    pub running: BTreeMap<String, DateTime<Utc>>,
+    /// The checks that are waiting for their next run, with the time it is due.
+    #[serde(skip)]
+    pub scheduled: BTreeMap<String, DateTime<Utc>>,
+    /// Whether the first round of health checks has completed since the monitor started.
+    #[serde(skip)]
+    pub startup_complete: bool,
+    /// When the current deployment phase was entered.
+    #[serde(skip, default = "Utc::now")]
+    pub phase_since: DateTime<Utc>,
+    /// The record of the changes to the state and the phase.
+    #[serde(skip)]
+    pub audit: AuditLog,
+    /// Notifies subscribers whenever the status changes.
+    #[serde(skip, default = "change_channel")]
+    changes: watch::Sender<()>,
 }
 
 impl Status {
     pub fn new() -> Self {
+        let phase = CONFIG.server.phase.clone();
+        let deployment = (phase == DeploymentPhase::Deploying).then(Deployment::new);
         Status {
             state: HealthState::Healthy,
             messages: Vec::new(),
-            phase: CONFIG.server.phase.clone(),
+            phase,
+            checks: BTreeMap::new(),
+            deployment,
+            release: Release::default(),
+            revision: 0,
+            boot: boot(),
+            history: VecDeque::new(),
+            running: BTreeMap::new(),
+            scheduled: BTreeMap::new(),
+            startup_complete: false,
+            phase_since: Utc::now(),
+            audit: AuditLog::default(),
+            changes: change_channel(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: HealthState) {
+        self.state = state;
+        self.notify();
+    }
+
+    pub fn set_phase(&mut self, phase: DeploymentPhase) {
+        if self.phase != phase {
+            self.phase_since = Utc::now();
+            if let Some(deployment) = self.deployment.take() {
+                self.finish_deployment(deployment, &phase);
+            }
+            self.deployment = (phase == DeploymentPhase::Deploying).then(Deployment::new);
+        }
+        self.phase = phase;
+        self.notify();
+    }
+
+    /// Returns the HTTP status code to report to load balancers. While the application is
+    /// draining or in maintenance it is taken out of rotation regardless of its health.
+    pub fn status_code(&self) -> StatusCode {
+        match self.phase {
+            DeploymentPhase::Deploying | DeploymentPhase::Online => self.state.into(),
+            DeploymentPhase::Draining | DeploymentPhase::Maintenance => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
         }
     }
 
     pub fn add_message(&mut self, message: String) {
         self.messages.push(message);
+        self.notify();
+    }
+
+    /// Updates the metadata of the deployment in progress. If the application is not being
+    /// deployed the release information applies to the running release.
+    pub fn update_deployment(&mut self, update: DeploymentUpdate) {
+        let release = Release {
+            version: update.version,
+            sha: update.sha,
+            deployer: update.deployer,
+        };
+        match &mut self.deployment {
+            Some(deployment) => {
+                deployment.release.merge(release);
+                if update.step.is_some() {
+                    deployment.step = update.step;
+                }
+                if update.progress.is_some() {
+                    deployment.progress = update.progress;
+                }
+            }
+            None => self.release.merge(release),
+        }
+        self.notify();
+    }
+
+    /// Returns the running release, the deployment in progress and the past deployments.
+    pub fn deployments(&self) -> Deployments {
+        Deployments {
+            release: self.release.clone(),
+            deployment: self.deployment.clone(),
+            history: self.history.iter().cloned().collect(),
+        }
+    }
+
+    /// Moves a deployment that ended into the history, keeping the history bounded.
+    fn finish_deployment(&mut self, deployment: Deployment, phase: &DeploymentPhase) {
+        let outcome = match (self.state, phase) {
+            (HealthState::Unhealthy, _) => DeploymentOutcome::Failed,
+            (HealthState::Healthy, DeploymentPhase::Online) => DeploymentOutcome::Succeeded,
+            (HealthState::Healthy, _) => DeploymentOutcome::Aborted,
+        };
+        if outcome == DeploymentOutcome::Succeeded {
+            self.release = deployment.release.clone();
+        }
+        self.history.push_back(DeploymentRecord {
+            duration: deployment.elapsed().as_secs(),
+            release: deployment.release,
+            started: deployment.started,
+            finished: Utc::now(),
+            outcome,
+        });
+        while self.history.len() > CONFIG.server.deploy_history {
+            self.history.pop_front();
+        }
     }
+
+    /// Records that a health check started running. This doesn't notify subscribers, since the
+    /// outcome is what matters to them.
+    pub fn start_check(&mut self, name: &str) {
+        self.running.insert(name.to_string(), Utc::now());
+    }
+
+    /// Records when a health check that is waiting for its next run is due. Like starting a check,
+    /// this doesn't notify subscribers.
+    pub fn schedule_check(&mut self, name: &str, due: DateTime<Utc>) {
+        self.scheduled.insert(name.to_string(), due);
+    }
+
+    /// Records that a health check is no longer waiting for its next run, because it is about to
+    /// run, or it stopped.
+    pub fn unschedule_check(&mut self, name: &str) {
+        self.scheduled.remove(name);
+    }
+
+    /// Returns whether the health checks stopped making progress: a check has been running for
+    /// longer than the grace period, or a check is overdue by more than the grace period, which
+    /// means its loop died.
+    pub fn checks_stalled(&self, grace: std::time::Duration) -> bool {
+        let cutoff = Utc::now() - chrono::Duration::from_std(grace).unwrap_or_default();
+        self.running
+            .values()
+            .chain(self.scheduled.values())
+            .any(|since| *since < cutoff)
+    }
+
+    /// Records the outcome of a health check run. Subscribers are notified when it differs from the
+    /// outcome of the previous run.
+    pub fn set_check_result(&mut self, name: &str, result: &Result<(), String>) {
+        self.running.remove(name);
+        let check_result = CheckResult {
+            passed: result.is_ok(),
+            last_run: Utc::now(),
+            error: result.as_ref().err().cloned(),
+        };
+        let changed = self.checks.get(name).is_none_or(|previous| {
+            previous.passed != check_result.passed || previous.error != check_result.error
+        });
+        self.checks.insert(name.to_string(), check_result);
+        if changed {
+            self.notify();
+        }
+    }
+
+    /// Marks the first round of health checks as completed. This is not part of the status, so it
+    /// notifies subscribers without changing the revision.
+    pub fn complete_startup(&mut self) {
+        self.startup_complete = true;
+        self.changes.send_replace(());
+    }
+
+    /// Returns the state, phase and number of messages, to find out afterwards what changed.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state,
+            phase: self.phase.clone(),
+            messages: self.messages.len(),
+        }
+    }
+
+    /// Adds a record of the changes made since the snapshot was taken to the audit log. Requests
+    /// are always recorded, other sources only when they changed the state or the phase.
+    pub fn audit(&mut self, before: Snapshot, source: Source, request_id: Option<String>) {
+        let state = (before.state != self.state).then_some(Change {
+            old: before.state,
+            new: self.state,
+        });
+        let phase = (before.phase != self.phase).then(|| Change {
+            old: before.phase,
+            new: self.phase.clone(),
+        });
+        if state.is_none() && phase.is_none() && !source.is_request() {
+            return;
+        }
+        let messages = self.messages.get(before.messages..).unwrap_or_default();
+        self.audit.record(AuditRecord {
+            timestamp: Utc::now(),
+            request_id,
+            source,
+            state,
+            phase,
+            message: (!messages.is_empty()).then(|| messages.join(", ")),
+        });
+    }
+
+    /// Returns a receiver that is marked as changed whenever the status is updated, so that
+    /// long-running consumers can react to changes without polling.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Returns the weak entity tag of the current revision, for conditional requests.
+    pub fn etag(&self) -> String {
+        format!("W/\"{}-{}\"", self.boot, self.revision)
+    }
+
+    fn notify(&mut self) {
+        self.revision += 1;
+        self.changes.send_replace(());
+    }
+}
+
+/// Returns the time the status was created, in milliseconds since the epoch in hexadecimal.
+fn boot() -> String {
+    format!("{:x}", Utc::now().timestamp_millis())
+}
+
+fn change_channel() -> watch::Sender<()> {
+    watch::Sender::new(())
+}
+
+/// The parts of the status that are audited, as they were at a point in time.
+pub struct Snapshot {
+    state: HealthState,
+    phase: DeploymentPhase,
+    messages: usize,
+}
+
+/// The outcome of the most recent run of a health check.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CheckResult {
+    pub passed: bool,
+    pub last_run: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Identifies a release of the monitored application. All fields are optional since a deploy
+/// script might only know some of them.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Release {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The git commit that was deployed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    /// The person or system that performed the deployment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployer: Option<String>,
+}
+
+impl Release {
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none() && self.sha.is_none() && self.deployer.is_none()
+    }
+
+    /// Overwrites the fields that are set in the given release.
+    fn merge(&mut self, release: Release) {
+        if release.version.is_some() {
+            self.version = release.version;
+        }
+        if release.sha.is_some() {
+            self.sha = release.sha;
+        }
+        if release.deployer.is_some() {
+            self.deployer = release.deployer;
+        }
+    }
+}
+
+impl fmt::Display for Release {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.version.as_deref().unwrap_or("unknown version")
+        )?;
+        if let Some(sha) = &self.sha {
+            write!(f, " ({})", sha)?;
+        }
+        if let Some(deployer) = &self.deployer {
+            write!(f, " by {}", deployer)?;
+        }
+        Ok(())
+    }
+}
+
+/// A deployment that is in progress.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Deployment {
+    #[serde(flatten)]
+    pub release: Release,
+    pub started: DateTime<Utc>,
+    /// The step the deploy script is currently executing, e.g. "database updates".
+    #[serde(default)]
+    pub step: Option<String>,
+    /// The progress of the deployment as a percentage.
+    #[serde(default)]
+    pub progress: Option<u8>,
+}
+
+impl Deployment {
+    pub fn new() -> Self {
+        Deployment {
+            release: Release::default(),
+            started: Utc::now(),
+            step: None,
+            progress: None,
+        }
+    }
+
+    /// Returns the time that has passed since the deployment started.
+    pub fn elapsed(&self) -> std::time::Duration {
+        (Utc::now() - self.started).to_std().unwrap_or_default()
+    }
+}
+
+/// The elapsed time is derived from the start time, and is included in the JSON output so that
+/// clients don't need to rely on their own clock.
+impl Serialize for Deployment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Output<'a> {
+            #[serde(flatten)]
+            release: &'a Release,
+            started: &'a DateTime<Utc>,
+            elapsed: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            step: &'a Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            progress: &'a Option<u8>,
+        }
+
+        Output {
+            release: &self.release,
+            started: &self.started,
+            elapsed: self.elapsed().as_secs(),
+            step: &self.step,
+            progress: &self.progress,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl fmt::Display for Deployment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deploying {} since {}",
+            self.release,
+            self.started.format("%Y-%m-%d %H:%M:%S")
+        )?;
+        if let Some(step) = &self.step {
+            write!(f, ", {}", step)?;
+        }
+        if let Some(progress) = self.progress {
+            write!(f, " ({}%)", progress)?;
+        }
+        Ok(())
+    }
+}
+
+/// A change to the status, as sent in a PATCH request. Only the fields that are set are changed.
+#[derive(Debug, Default, Serialize)]
+pub struct StatusPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthState>,
+    /// A message that is added to the status messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<DeploymentPhase>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<DeploymentUpdate>,
+}
+
+/// Changes to the metadata of a deployment, as sent along with a phase change.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeploymentUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u8>,
+}
+
+impl DeploymentUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none()
+            && self.sha.is_none()
+            && self.deployer.is_none()
+            && self.step.is_none()
+            && self.progress.is_none()
+    }
+}
+
+impl From<crate::cli::ReleaseArgs> for DeploymentUpdate {
+    fn from(args: crate::cli::ReleaseArgs) -> Self {
+        DeploymentUpdate {
+            version: args.version,
+            sha: args.sha,
+            deployer: args.deployer,
+            ..Default::default()
+        }
+    }
+}
+
+/// A deployment that has ended.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DeploymentRecord {
+    #[serde(flatten)]
+    pub release: Release,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    /// The duration of the deployment in seconds.
+    pub duration: u64,
+    pub outcome: DeploymentOutcome,
+}
+
+impl fmt::Display for DeploymentRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} in {}s",
+            self.started.format("%Y-%m-%d %H:%M:%S"),
+            self.release,
+            self.outcome,
+            self.duration
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum DeploymentOutcome {
+    /// The application was put online in a healthy state.
+    #[serde(rename = "succeeded")]
+    Succeeded,
+    /// The application was unhealthy when the deployment ended.
+    #[serde(rename = "failed")]
+    Failed,
+    /// The deployment was abandoned by moving to another phase than online.
+    #[serde(rename = "aborted")]
+    Aborted,
+}
+
+impl fmt::Display for DeploymentOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            DeploymentOutcome::Succeeded => "succeeded",
+            DeploymentOutcome::Failed => "failed",
+            DeploymentOutcome::Aborted => "aborted",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+/// The deployments of the monitored application, as served on `/deployments`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Deployments {
+    /// The release that is currently running.
+    #[serde(default)]
+    pub release: Release,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<Deployment>,
+    pub history: Vec<DeploymentRecord>,
 }
 
 impl fmt::Display for Status {
@@ -46,6 +552,35 @@ pub enum DeploymentPhase {
     Deploying,
     #[serde(rename = "online")]
     Online,
+    #[serde(rename = "draining")]
+    Draining,
+    #[serde(rename = "maintenance")]
+    Maintenance,
+}
+
+impl DeploymentPhase {
+    /// Returns whether the application can move from this phase to the given phase. Staying in
+    /// the same phase is always allowed. The other transitions are:
+    ///
+    /// - online to deploying: a deployment starts.
+    /// - online to draining: the instance is taken out of rotation before it is stopped.
+    /// - online to maintenance: the instance is taken out of rotation for a while.
+    /// - deploying to online: the deployment is finished.
+    /// - deploying to maintenance: the deployment is aborted, and the instance needs attention.
+    /// - draining to online: the drain is cancelled. Once an instance is draining it is on its way
+    ///   out, so this is the only way back.
+    /// - maintenance to online: the maintenance is over.
+    /// - maintenance to deploying: a deployment starts during the maintenance.
+    pub fn can_transition_to(&self, phase: &DeploymentPhase) -> bool {
+        use DeploymentPhase::*;
+        matches!(
+            (self, phase),
+            (Online, Deploying | Draining | Maintenance)
+                | (Deploying, Online | Maintenance)
+                | (Draining, Online)
+                | (Maintenance, Online | Deploying)
+        ) || self == phase
+    }
 }
 
 impl From<crate::cli::DeploymentPhase> for DeploymentPhase {
@@ -53,6 +588,8 @@ impl From<crate::cli::DeploymentPhase> for DeploymentPhase {
         match arg {
             crate::cli::DeploymentPhase::Deploying => DeploymentPhase::Deploying,
             crate::cli::DeploymentPhase::Online => DeploymentPhase::Online,
+            crate::cli::DeploymentPhase::Draining => DeploymentPhase::Draining,
+            crate::cli::DeploymentPhase::Maintenance => DeploymentPhase::Maintenance,
         }
     }
 }
@@ -63,6 +600,8 @@ impl TryFrom<&str> for DeploymentPhase {
         match value.to_lowercase().as_str() {
             "deploying" => Ok(DeploymentPhase::Deploying),
             "online" => Ok(DeploymentPhase::Online),
+            "draining" => Ok(DeploymentPhase::Draining),
+            "maintenance" => Ok(DeploymentPhase::Maintenance),
             _ => Err("Invalid deployment phase"),
         }
     }
@@ -73,6 +612,8 @@ impl fmt::Display for DeploymentPhase {
         let phase = match self {
             DeploymentPhase::Deploying => "deploying",
             DeploymentPhase::Online => "online",
+            DeploymentPhase::Draining => "draining",
+            DeploymentPhase::Maintenance => "maintenance",
         };
         write!(f, "{}", phase)
     }
@@ -145,6 +686,291 @@ mod tests {
         assert_eq!(status.messages[0], "Test message");
     }
 
+    #[test]
+    fn test_set_check_result() {
+        let mut status = Status::new();
+        status.set_check_result("FileCheck", &Ok(()));
+        assert!(status.checks["FileCheck"].passed);
+        assert_eq!(status.checks["FileCheck"].error, None);
+
+        status.set_check_result("FileCheck", &Err("File is empty".to_string()));
+        assert!(!status.checks["FileCheck"].passed);
+        assert_eq!(
+            status.checks["FileCheck"].error,
+            Some("File is empty".to_string())
+        );
+
+        // Only runs with a different outcome change the revision.
+        assert_eq!(status.revision, 2);
+        status.set_check_result("FileCheck", &Err("File is empty".to_string()));
+        assert_eq!(status.revision, 2);
+    }
+
+    #[test]
+    fn test_audit() {
+        let mut status = Status::new();
+        let check = Source::Check {
+            name: "FileCheck".to_string(),
+        };
+
+        // A check that doesn't change anything is not recorded.
+        let before = status.snapshot();
+        status.audit(before, check.clone(), None);
+        assert!(status.audit.records().is_empty());
+
+        let before = status.snapshot();
+        status.set_state(HealthState::Unhealthy);
+        status.add_message("FileCheck: missing".to_string());
+        status.audit(before, check, None);
+        let records = status.audit.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].state,
+            Some(Change {
+                old: HealthState::Healthy,
+                new: HealthState::Unhealthy
+            })
+        );
+        assert_eq!(records[0].phase, None);
+        assert_eq!(records[0].message.as_deref(), Some("FileCheck: missing"));
+
+        // Requests are recorded even when they don't change anything.
+        let source = Source::Api {
+            client: None,
+            token: None,
+        };
+        let before = status.snapshot();
+        status.audit(before, source.clone(), Some("abc".to_string()));
+        let records = status.audit.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].source, source);
+        assert_eq!(records[1].request_id.as_deref(), Some("abc"));
+        assert_eq!(records[1].state, None);
+        assert_eq!(records[1].message, None);
+    }
+
+    #[test]
+    fn test_running_checks() {
+        let mut status = Status::new();
+        status.start_check("FileCheck");
+        status.start_check("UrlCheck");
+        assert_eq!(
+            status.running.keys().collect::<Vec<_>>(),
+            ["FileCheck", "UrlCheck"]
+        );
+
+        status.set_check_result("FileCheck", &Ok(()));
+        assert_eq!(status.running.keys().collect::<Vec<_>>(), ["UrlCheck"]);
+        status.set_check_result("UrlCheck", &Ok(()));
+        assert!(status.running.is_empty());
+    }
+
+    #[test]
+    fn test_checks_stalled() {
+        let grace = std::time::Duration::from_secs(60);
+        let mut status = Status::new();
+        assert!(!status.checks_stalled(grace));
+
+        // A check that is due in the future, or overdue within the grace period, is fine.
+        status.schedule_check("FileCheck", Utc::now() + chrono::Duration::seconds(300));
+        status.schedule_check("UrlCheck", Utc::now() - chrono::Duration::seconds(30));
+        assert!(!status.checks_stalled(grace));
+
+        // A check loop that doesn't run its check long after it is due has died.
+        status.schedule_check("UrlCheck", Utc::now() - chrono::Duration::seconds(120));
+        assert!(status.checks_stalled(grace));
+        status.unschedule_check("UrlCheck");
+        assert!(!status.checks_stalled(grace));
+
+        // So has a check that runs for too long.
+        status.running.insert(
+            "UrlCheck".to_string(),
+            Utc::now() - chrono::Duration::seconds(120),
+        );
+        assert!(status.checks_stalled(grace));
+    }
+
+    #[test]
+    fn test_phase_transitions() {
+        use DeploymentPhase::*;
+        let phases = [Deploying, Online, Draining, Maintenance];
+        for phase in &phases {
+            assert!(phase.can_transition_to(phase));
+        }
+        let allowed = [
+            (Online, Deploying),
+            (Online, Draining),
+            (Online, Maintenance),
+            (Deploying, Online),
+            (Deploying, Maintenance),
+            (Draining, Online),
+            (Maintenance, Online),
+            (Maintenance, Deploying),
+        ];
+        for (current, next) in &allowed {
+            assert!(current.can_transition_to(next), "{} to {}", current, next);
+        }
+
+        // Every other transition is rejected.
+        let rejected = [
+            (Deploying, Draining),
+            (Draining, Deploying),
+            (Draining, Maintenance),
+            (Maintenance, Draining),
+        ];
+        for (current, next) in &rejected {
+            assert!(!current.can_transition_to(next), "{} to {}", current, next);
+        }
+        assert_eq!(
+            allowed.len() + rejected.len(),
+            phases.len() * (phases.len() - 1)
+        );
+    }
+
+    #[test]
+    fn test_status_code() {
+        let mut status = Status::new();
+        assert_eq!(status.status_code(), StatusCode::OK);
+
+        status.set_phase(DeploymentPhase::Deploying);
+        assert_eq!(status.status_code(), StatusCode::OK);
+
+        status.set_phase(DeploymentPhase::Maintenance);
+        assert_eq!(status.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        status.set_phase(DeploymentPhase::Draining);
+        assert_eq!(status.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+
+        status.set_phase(DeploymentPhase::Online);
+        status.set_state(HealthState::Unhealthy);
+        assert_eq!(status.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_deployment() {
+        let mut status = Status::new();
+        assert_eq!(status.deployment, None);
+
+        status.set_phase(DeploymentPhase::Deploying);
+        let deployment = status.deployment.clone().unwrap();
+        assert!(deployment.elapsed().as_secs() < 1);
+
+        // The deployment keeps its start time while the application remains in the phase.
+        status.set_phase(DeploymentPhase::Deploying);
+        assert_eq!(status.deployment, Some(deployment));
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["deployment"]["elapsed"], 0);
+        assert!(json["deployment"]["started"].is_string());
+
+        status.set_phase(DeploymentPhase::Online);
+        assert_eq!(status.deployment, None);
+        assert!(serde_json::to_value(&status).unwrap()["deployment"].is_null());
+    }
+
+    #[test]
+    fn test_deployment_history() {
+        let mut status = Status::new();
+        status.set_phase(DeploymentPhase::Deploying);
+        status.update_deployment(DeploymentUpdate {
+            version: Some("1.2.0".to_string()),
+            deployer: Some("jenkins".to_string()),
+            step: Some("database updates".to_string()),
+            progress: Some(40),
+            ..Default::default()
+        });
+        let deployment = status.deployment.clone().unwrap();
+        assert_eq!(deployment.step, Some("database updates".to_string()));
+        assert_eq!(deployment.progress, Some(40));
+
+        // Metadata sent along with the phase change completes the release information.
+        status.update_deployment(DeploymentUpdate {
+            sha: Some("8f3c2a1".to_string()),
+            ..Default::default()
+        });
+        status.set_phase(DeploymentPhase::Online);
+        assert_eq!(status.release.to_string(), "1.2.0 (8f3c2a1) by jenkins");
+        assert_eq!(status.history.len(), 1);
+        assert_eq!(status.history[0].outcome, DeploymentOutcome::Succeeded);
+        assert_eq!(status.history[0].release, status.release);
+
+        // A deployment that ends while the application is unhealthy has failed, and does not
+        // change the running release.
+        status.set_phase(DeploymentPhase::Deploying);
+        status.update_deployment(DeploymentUpdate {
+            version: Some("1.3.0".to_string()),
+            ..Default::default()
+        });
+        status.set_state(HealthState::Unhealthy);
+        status.set_phase(DeploymentPhase::Online);
+        assert_eq!(status.history[1].outcome, DeploymentOutcome::Failed);
+        assert_eq!(status.release.version, Some("1.2.0".to_string()));
+
+        status.set_state(HealthState::Healthy);
+        status.set_phase(DeploymentPhase::Deploying);
+        status.set_phase(DeploymentPhase::Maintenance);
+        assert_eq!(status.history[2].outcome, DeploymentOutcome::Aborted);
+
+        // The history is bounded.
+        for _ in 0..CONFIG.server.deploy_history {
+            status.set_phase(DeploymentPhase::Deploying);
+            status.set_phase(DeploymentPhase::Online);
+        }
+        assert_eq!(status.history.len(), CONFIG.server.deploy_history);
+        assert_eq!(
+            status.deployments().history.len(),
+            CONFIG.server.deploy_history
+        );
+    }
+
+    #[test]
+    fn test_update_release_without_deployment() {
+        let mut status = Status::new();
+        status.update_deployment(DeploymentUpdate {
+            version: Some("1.2.0".to_string()),
+            step: Some("ignored".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(status.release.version, Some("1.2.0".to_string()));
+        assert_eq!(status.deployment, None);
+        assert!(status.history.is_empty());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut status = Status::new();
+        let receiver = status.subscribe();
+        assert!(!receiver.has_changed().unwrap());
+
+        status.set_state(HealthState::Unhealthy);
+        assert!(receiver.has_changed().unwrap());
+
+        // Followers of the status, like the dashboard, see the outcome of the checks change.
+        let mut receiver = status.subscribe();
+        status.set_check_result("FileCheck", &Ok(()));
+        assert!(receiver.has_changed().unwrap());
+        receiver.mark_unchanged();
+        status.set_check_result("FileCheck", &Ok(()));
+        assert!(!receiver.has_changed().unwrap());
+        status.set_check_result("FileCheck", &Err("File is empty".to_string()));
+        assert!(receiver.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_etag() {
+        let mut status = Status::new();
+        let etag = status.etag();
+        assert!(etag.starts_with("W/\"") && etag.ends_with("-0\""));
+        status.complete_startup();
+        assert_eq!(status.etag(), etag);
+        status.add_message("Deployed".to_string());
+        assert_eq!(status.etag(), etag.replace("-0\"", "-1\""));
+
+        // The revisions of a new run of the monitor are different.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert_ne!(Status::new().etag(), etag);
+    }
+
     #[test]
     fn test_to_string() {
         let mut status = Status::new();
@@ -0,0 +1,404 @@
+/// This module runs the monitored application as a child process and ties the health status to
+/// its lifecycle. When the application exits the instance is marked as unhealthy, with the last
+/// lines of its output as message. Depending on the restart policy it is then restarted.
+use crate::audit::Source;
+use crate::process::{drain, exit_code, forward, OutputTail, DRAIN_TIMEOUT};
+use crate::status::{HealthState, Status};
+
+use log::{debug, error, info, warn};
+use nix::sys::signal::Signal;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, Mutex};
+
+/// The number of lines of output to include in the status message when the application exits.
+const OUTPUT_LINES: usize = 5;
+/// The maximum delay between restarts. An application that ran for longer than this before it
+/// exited is restarted after the initial backoff again.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    /// Never restart the application.
+    Never,
+    /// Restart the application when it exits with a non-zero exit code.
+    OnFailure,
+    /// Always restart the application when it exits.
+    Always,
+}
+
+impl From<crate::cli::RestartPolicy> for RestartPolicy {
+    fn from(arg: crate::cli::RestartPolicy) -> Self {
+        match arg {
+            crate::cli::RestartPolicy::Never => RestartPolicy::Never,
+            crate::cli::RestartPolicy::OnFailure => RestartPolicy::OnFailure,
+            crate::cli::RestartPolicy::Always => RestartPolicy::Always,
+        }
+    }
+}
+
+pub struct Supervisor {
+    command: Vec<String>,
+    restart: RestartPolicy,
+    max_restarts: Option<u32>,
+    backoff: Duration,
+}
+
+impl Supervisor {
+    pub fn new(
+        command: Vec<String>,
+        restart: RestartPolicy,
+        max_restarts: Option<u32>,
+        backoff: Duration,
+    ) -> Self {
+        Supervisor {
+            command,
+            restart,
+            max_restarts,
+            backoff,
+        }
+    }
+
+    /// Runs the application until it exits for good, or until it is stopped by one of the given
+    /// signals, which are forwarded to the application. Returns the exit code of the application.
+    ///
+    /// If the application exits and is not restarted, the health monitor keeps reporting it as
+    /// unhealthy until it is asked to shut down.
+    pub async fn run(
+        &self,
+        status: Arc<Mutex<Status>>,
+        mut signals: mpsc::UnboundedReceiver<Signal>,
+    ) -> i32 {
+        let output = OutputTail::new(OUTPUT_LINES);
+        let mut restarts = 0;
+        let mut backoff = self.backoff;
+
+        loop {
+            output.clear();
+            let started = Instant::now();
+            let (code, stopped_by) = match self.spawn(&output) {
+                Ok((mut child, captures)) => {
+                    info!("Started {}.", self.command.join(" "));
+                    // Only undo the unhealthy state we set ourselves. If it was set by somebody else,
+                    // e.g. a failed check or an operator, it still applies.
+                    let mut status = status.lock().await;
+                    if restarts > 0
+                        && status.state == HealthState::Unhealthy
+                        && status.audit.last_state_change() == Some(&source())
+                    {
+                        let before = status.snapshot();
+                        status.set_state(HealthState::Healthy);
+                        status.audit(before, source(), None);
+                    }
+                    drop(status);
+
+                    let mut stopped_by = None;
+                    let exit = loop {
+                        let signal = tokio::select! {
+                            exit = child.wait() => break exit,
+                            Some(signal) = signals.recv() => signal,
+                        };
+                        // Give the application the chance to shut down gracefully, but kill it if
+                        // we are asked to stop a second time.
+                        if stopped_by.replace(signal).is_some() {
+                            warn!("Killing {}.", self.command.join(" "));
+                            let _ = child.start_kill();
+                        } else {
+                            info!("Forwarding {} to {}.", signal, self.command.join(" "));
+                            forward(&child, signal);
+                        }
+                    };
+                    drain(captures, DRAIN_TIMEOUT).await;
+                    let code = match exit {
+                        Ok(exit) => exit_code(exit),
+                        Err(e) => {
+                            warn!("Failed to wait for {}: {}", self.command.join(" "), e);
+                            1
+                        }
+                    };
+                    (code, stopped_by)
+                }
+                Err(e) => {
+                    output.push(format!("Failed to start {}: {}", self.command[0], e));
+                    (127, None)
+                }
+            };
+
+            if let Some(signal) = stopped_by {
+                info!(
+                    "The application exited with code {} after {}.",
+                    code, signal
+                );
+                return code;
+            }
+
+            let message = self.exit_message(code, &output.lines());
+            error!("{}", message);
+            {
+                let mut status = status.lock().await;
+                let before = status.snapshot();
+                status.set_state(HealthState::Unhealthy);
+                status.add_message(message);
+                status.audit(before, source(), None);
+            }
+
+            if !self.should_restart(code, restarts) {
+                info!("The application is not restarted, waiting for a signal to shut down.");
+                signals.recv().await;
+                return code;
+            }
+
+            // Back off exponentially when the application keeps crashing.
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = self.backoff;
+            }
+            restarts += 1;
+            info!(
+                "Restarting the application in {:?} (restart {}).",
+                backoff, restarts
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = signals.recv() => return code,
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Starts the application and captures its output. It runs in its own process group so that
+    /// signals sent to the terminal reach it only once, through us.
+    fn spawn(
+        &self,
+        output: &OutputTail,
+    ) -> std::io::Result<(Child, Vec<tokio::task::JoinHandle<()>>)> {
+        debug!("Starting {:?}", self.command);
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+        let captures = vec![
+            output.capture(child.stdout.take().unwrap(), tokio::io::stdout()),
+            output.capture(child.stderr.take().unwrap(), tokio::io::stderr()),
+        ];
+        Ok((child, captures))
+    }
+
+    fn should_restart(&self, code: i32, restarts: u32) -> bool {
+        let allowed = match self.restart {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => code != 0,
+            RestartPolicy::Always => true,
+        };
+        allowed && self.max_restarts.is_none_or(|max| restarts < max)
+    }
+
+    /// Returns the status message for an application that exited.
+    fn exit_message(&self, code: i32, output: &[String]) -> String {
+        let mut message = format!(
+            "The application `{}` exited with code {}.",
+            self.command.join(" "),
+            code
+        );
+        if !output.is_empty() {
+            message.push('\n');
+            message.push_str(&output.join("\n"));
+        }
+        message
+    }
+}
+
+/// The source of the changes the supervisor makes, in the audit log.
+fn source() -> Source {
+    Source::System {
+        name: "supervisor".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+    use tokio::time::timeout;
+
+    fn supervisor(script: &str, restart: RestartPolicy, max_restarts: Option<u32>) -> Supervisor {
+        Supervisor::new(
+            vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            restart,
+            max_restarts,
+            Duration::from_millis(10),
+        )
+    }
+
+    /// Waits until the application is reported as unhealthy.
+    async fn wait_until_unhealthy(status: &Arc<Mutex<Status>>) {
+        let mut changes = status.lock().await.subscribe();
+        timeout(Duration::from_secs(5), async {
+            while status.lock().await.state == HealthState::Healthy {
+                changes.changed().await.unwrap();
+            }
+        })
+        .await
+        .expect("The application should become unhealthy.");
+    }
+
+    #[tokio::test]
+    async fn test_exit_marks_unhealthy() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (signals, received) = mpsc::unbounded_channel();
+        let supervisor = supervisor(
+            "echo Starting; echo 'Out of memory' >&2; exit 2",
+            RestartPolicy::Never,
+            None,
+        );
+        let run = supervisor.run(status.clone(), received);
+        tokio::pin!(run);
+
+        tokio::select! {
+            _ = &mut run => panic!("The supervisor should wait for a shutdown."),
+            _ = wait_until_unhealthy(&status) => {}
+        }
+        let message = status.lock().await.messages[0].clone();
+        assert!(message.starts_with("The application `sh -c "));
+        assert!(message.contains("exited with code 2."));
+        assert!(message.contains("\nStarting"));
+        assert!(message.contains("\nOut of memory"));
+
+        signals.send(Signal::SIGTERM).unwrap();
+        assert_eq!(timeout(Duration::from_secs(5), run).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exit_with_background_process() {
+        // The process in the background keeps the output open after the application exited.
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (signals, received) = mpsc::unbounded_channel();
+        let supervisor = supervisor("sleep 30 & exit 2", RestartPolicy::Never, None);
+        let run = supervisor.run(status.clone(), received);
+        tokio::pin!(run);
+
+        tokio::select! {
+            _ = &mut run => panic!("The supervisor should wait for a shutdown."),
+            _ = wait_until_unhealthy(&status) => {}
+        }
+        signals.send(Signal::SIGTERM).unwrap();
+        assert_eq!(timeout(Duration::from_secs(5), run).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_restart_on_failure() {
+        let file = NamedTempFile::new().unwrap();
+        let script = format!("echo run >> {}; exit 1", file.path().display());
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (signals, received) = mpsc::unbounded_channel();
+        let supervisor = supervisor(&script, RestartPolicy::OnFailure, Some(2));
+        let run = supervisor.run(status.clone(), received);
+        tokio::pin!(run);
+
+        // The application is started once and restarted twice, then given up on.
+        let runs = || {
+            std::fs::read_to_string(file.path())
+                .unwrap()
+                .lines()
+                .count()
+        };
+        timeout(Duration::from_secs(5), async {
+            while runs() < 3 {
+                tokio::select! {
+                    _ = &mut run => panic!("The supervisor should wait for a shutdown."),
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(timeout(Duration::from_millis(200), &mut run).await.is_err());
+        assert_eq!(runs(), 3);
+        assert_eq!(status.lock().await.state, HealthState::Unhealthy);
+        assert_eq!(status.lock().await.messages.len(), 3);
+
+        signals.send(Signal::SIGTERM).unwrap();
+        assert_eq!(timeout(Duration::from_secs(5), run).await.unwrap(), 1);
+    }
+
+    /// Returns a script that fails on its first run, and keeps running after a restart.
+    fn crash_once(file: &NamedTempFile) -> String {
+        let path = file.path().display();
+        format!("if [ -s {path} ]; then exec sleep 30; else echo run > {path}; exit 1; fi")
+    }
+
+    /// Runs the supervisor until the application was restarted, and returns the health state.
+    async fn state_after_restart(status: &Arc<Mutex<Status>>, script: &str) -> HealthState {
+        let (signals, received) = mpsc::unbounded_channel();
+        let supervisor = supervisor(script, RestartPolicy::OnFailure, None);
+        let run = supervisor.run(status.clone(), received);
+        tokio::pin!(run);
+        // The restarted application runs until it is stopped.
+        assert!(timeout(Duration::from_millis(500), &mut run).await.is_err());
+        let state = status.lock().await.state;
+        signals.send(Signal::SIGTERM).unwrap();
+        timeout(Duration::from_secs(5), run).await.unwrap();
+        state
+    }
+
+    #[tokio::test]
+    async fn test_restart_marks_healthy() {
+        let file = NamedTempFile::new().unwrap();
+        let status = Arc::new(Mutex::new(Status::new()));
+        let state = state_after_restart(&status, &crash_once(&file)).await;
+        assert_eq!(state, HealthState::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_restart_keeps_unhealthy_state_of_others() {
+        let file = NamedTempFile::new().unwrap();
+        let status = Arc::new(Mutex::new(Status::new()));
+        {
+            let mut status = status.lock().await;
+            let before = status.snapshot();
+            status.set_state(HealthState::Unhealthy);
+            let source = Source::Check {
+                name: "FileCheck".to_string(),
+            };
+            status.audit(before, source, None);
+        }
+        let state = state_after_restart(&status, &crash_once(&file)).await;
+        assert_eq!(state, HealthState::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_is_forwarded() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let (signals, received) = mpsc::unbounded_channel();
+        let supervisor = supervisor(
+            "trap 'exit 0' TERM; while true; do sleep 0.05; done",
+            RestartPolicy::Always,
+            None,
+        );
+        let run = supervisor.run(status.clone(), received);
+        tokio::pin!(run);
+
+        assert!(timeout(Duration::from_millis(200), &mut run).await.is_err());
+        signals.send(Signal::SIGTERM).unwrap();
+        assert_eq!(timeout(Duration::from_secs(5), run).await.unwrap(), 0);
+        assert_eq!(status.lock().await.state, HealthState::Healthy);
+    }
+
+    #[test]
+    fn test_should_restart() {
+        let never = supervisor("true", RestartPolicy::Never, None);
+        assert!(!never.should_restart(1, 0));
+
+        let on_failure = supervisor("true", RestartPolicy::OnFailure, Some(1));
+        assert!(on_failure.should_restart(1, 0));
+        assert!(!on_failure.should_restart(0, 0));
+        assert!(!on_failure.should_restart(1, 1));
+
+        let always = supervisor("true", RestartPolicy::Always, None);
+        assert!(always.should_restart(0, 100));
+    }
+}
@@ -0,0 +1,264 @@
+/// This module keeps systemd informed about the health monitor when it runs as a `Type=notify`
+/// service. The protocol is a handful of `KEY=value` lines sent as datagrams to the Unix socket in
+/// the `NOTIFY_SOCKET` environment variable. When the variable is not set nothing is sent.
+use crate::status::Status;
+
+use log::{debug, warn};
+use std::env;
+use std::io;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+}
+
+impl Notifier {
+    /// Returns a notifier for the socket systemd passed to us, if any.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        match Notifier::new(&path) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                warn!("Failed to open the notify socket {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Creates a notifier for the given socket path. A path starting with `@` refers to a socket
+    /// in the abstract namespace.
+    pub fn new(path: &str) -> io::Result<Self> {
+        let address = match path.strip_prefix('@') {
+            Some(name) => abstract_address(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Notifier {
+            socket: UnixDatagram::unbound()?,
+            address,
+        })
+    }
+
+    /// Sends the given state lines to systemd.
+    pub fn notify(&self, state: &str) {
+        debug!("Notifying systemd: {:?}", state);
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            warn!("Failed to notify systemd: {}", e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_address(name: &str) -> io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_address(_name: &str) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// Returns the watchdog interval systemd expects us to ping within, if the watchdog is enabled for
+/// this process.
+pub fn watchdog_from_env() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Returns the status line shown by `systemctl status`, e.g. "healthy (online)". Until the first
+/// round of health checks has completed, it tells how many of them have. The messages are left
+/// out: they can span several lines, and every line is an assignment in the protocol.
+fn status_line(status: &Status) -> String {
+    if status.startup_complete {
+        format!("{} ({})", status.state, status.phase)
+    } else {
+        format!(
+            "{} ({}), waiting for the first health checks, {} completed",
+            status.state,
+            status.phase,
+            status.checks.len()
+        )
+    }
+}
+
+/// Reports the status to systemd until the task is aborted. The service is reported as ready right
+/// away, since the server is listening by the time this starts. The health checks don't run while
+/// the application is being deployed, so waiting for them could keep systemd waiting forever.
+///
+/// When the watchdog is enabled it is pinged at half the interval, as long as the health checks make
+/// progress: no check has been running for longer than the interval, and no check is overdue by
+/// more than the interval. A check that hangs or a check loop that died stops the pings, so systemd
+/// restarts the monitor.
+pub async fn run(notifier: Arc<Notifier>, status: Arc<Mutex<Status>>, watchdog: Option<Duration>) {
+    let mut changes = status.lock().await.subscribe();
+    let mut ready = false;
+    let mut last_status = String::new();
+    let mut interval =
+        tokio::time::interval(watchdog.map_or(Duration::from_secs(3600), |watchdog| watchdog / 2));
+
+    loop {
+        let line = status_line(&*status.lock().await);
+
+        let mut state = Vec::new();
+        if !ready {
+            ready = true;
+            state.push("READY=1".to_string());
+        }
+        if line != last_status {
+            state.push(format!("STATUS={}", line));
+            last_status = line;
+        }
+        if !state.is_empty() {
+            notifier.notify(&state.join("\n"));
+        }
+
+        tokio::select! {
+            _ = changes.changed() => {}
+            _ = interval.tick(), if watchdog.is_some() => {
+                let watchdog = watchdog.unwrap();
+                if status.lock().await.checks_stalled(watchdog) {
+                    warn!(
+                        "The health checks made no progress for more than {:?}, not pinging the \
+                         watchdog.",
+                        watchdog
+                    );
+                } else {
+                    notifier.notify("WATCHDOG=1");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{DeploymentPhase, HealthState};
+    use tempfile::TempDir;
+    use tokio::net::UnixDatagram;
+    use tokio::time::timeout;
+
+    /// A stand-in for the socket systemd listens on.
+    struct Systemd {
+        _dir: TempDir,
+        socket: UnixDatagram,
+        path: String,
+    }
+
+    impl Systemd {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("notify").to_string_lossy().to_string();
+            let socket = UnixDatagram::bind(&path).unwrap();
+            Systemd {
+                _dir: dir,
+                socket,
+                path,
+            }
+        }
+
+        async fn receive(&self) -> String {
+            let mut buffer = [0; 1024];
+            let length = timeout(Duration::from_secs(5), self.socket.recv(&mut buffer))
+                .await
+                .expect("A notification should be sent.")
+                .unwrap();
+            String::from_utf8_lossy(&buffer[..length]).to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready_and_status() {
+        let systemd = Systemd::new();
+        let status = Arc::new(Mutex::new(Status::new()));
+        let task = tokio::spawn(run(
+            Arc::new(Notifier::new(&systemd.path).unwrap()),
+            status.clone(),
+            None,
+        ));
+
+        // The service is ready once the server listens, and the status line follows the first
+        // round of health checks.
+        assert_eq!(
+            systemd.receive().await,
+            "READY=1\nSTATUS=healthy (online), waiting for the first health checks, 0 completed"
+        );
+        status.lock().await.set_check_result("FileCheck", &Ok(()));
+        assert_eq!(
+            systemd.receive().await,
+            "STATUS=healthy (online), waiting for the first health checks, 1 completed"
+        );
+        status.lock().await.complete_startup();
+        assert_eq!(systemd.receive().await, "STATUS=healthy (online)");
+
+        status.lock().await.set_phase(DeploymentPhase::Maintenance);
+        assert_eq!(systemd.receive().await, "STATUS=healthy (maintenance)");
+
+        // Messages can't inject assignments.
+        {
+            let mut status = status.lock().await;
+            status.set_state(HealthState::Unhealthy);
+            status.add_message("Application exited:\nREADY=1\nMAINPID=1".to_string());
+        }
+        assert_eq!(systemd.receive().await, "STATUS=unhealthy (maintenance)");
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_watchdog() {
+        let systemd = Systemd::new();
+        let status = Arc::new(Mutex::new(Status::new()));
+        status.lock().await.complete_startup();
+        let watchdog = Duration::from_millis(100);
+        let task = tokio::spawn(run(
+            Arc::new(Notifier::new(&systemd.path).unwrap()),
+            status.clone(),
+            Some(watchdog),
+        ));
+
+        assert_eq!(systemd.receive().await, "READY=1\nSTATUS=healthy (online)");
+        assert_eq!(systemd.receive().await, "WATCHDOG=1");
+
+        // A check that runs for longer than the watchdog interval stops the pings.
+        status.lock().await.start_check("FileCheck");
+        tokio::time::sleep(watchdog * 2).await;
+        let mut buffer = [0; 1024];
+        while systemd.socket.try_recv(&mut buffer).is_ok() {}
+        assert!(timeout(watchdog * 2, systemd.receive()).await.is_err());
+
+        // The pings resume when it finishes.
+        status.lock().await.set_check_result("FileCheck", &Ok(()));
+        assert_eq!(systemd.receive().await, "WATCHDOG=1");
+
+        // A check loop that died doesn't run its check when it is due, which also stops them.
+        status
+            .lock()
+            .await
+            .schedule_check("FileCheck", chrono::Utc::now());
+        tokio::time::sleep(watchdog * 2).await;
+        while systemd.socket.try_recv(&mut buffer).is_ok() {}
+        assert!(timeout(watchdog * 2, systemd.receive()).await.is_err());
+
+        task.abort();
+    }
+
+    #[test]
+    fn test_missing_socket() {
+        let notifier = Notifier::new("/nonexistent/notify").unwrap();
+        // Failing to notify is not fatal.
+        notifier.notify("READY=1");
+    }
+}
@@ -0,0 +1,167 @@
+/// This module terminates TLS for the server, when the scheme is `https`. The certificate is
+/// reloaded when its files change, so renewing it doesn't need a restart. Optionally clients need
+/// a certificate signed by the configured CA (mutual TLS).
+use crate::config::TlsConfig;
+
+use axum_server::tls_rustls::RustlsConfig;
+use log::{info, warn};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How often to check whether the certificate files changed.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reads the certificates in a PEM file.
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}.", path));
+    }
+    Ok(certs)
+}
+
+/// Reads the private key in a PEM file.
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| format!("Invalid private key in {}: {}", path, e))?
+        .ok_or_else(|| format!("No private key found in {}.", path))
+}
+
+/// Builds the TLS configuration of the server from the configured files.
+pub fn server_config(config: &TlsConfig) -> Result<ServerConfig, String> {
+    let (Some(cert), Some(key)) = (&config.cert, &config.key) else {
+        return Err("A certificate and a private key are needed to serve https.".to_string());
+    };
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+
+    let builder = if config.client_auth {
+        let Some(ca) = &config.ca else {
+            return Err("A CA is needed to verify client certificates.".to_string());
+        };
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca)? {
+            roots
+                .add(cert)
+                .map_err(|e| format!("Invalid CA {}: {}", ca, e))?;
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| format!("Invalid CA {}: {}", ca, e))?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    let mut server_config = builder
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map_err(|e| format!("Invalid certificate {}: {}", cert, e))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+/// Returns the modification times of the configured files.
+fn modified(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [&config.cert, &config.key, &config.ca]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Reloads the TLS configuration whenever one of the files changes. When the new files are
+/// invalid, e.g. because the key has not been written yet, the previous configuration is kept.
+pub async fn watch(rustls_config: RustlsConfig, config: &'static TlsConfig) {
+    let mut last_modified = modified(config);
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        let current = modified(config);
+        if current == last_modified {
+            continue;
+        }
+        match server_config(config) {
+            Ok(server_config) => {
+                rustls_config.reload_from_config(Arc::new(server_config));
+                last_modified = current;
+                info!("Reloaded the TLS certificate.");
+            }
+            Err(e) => warn!("Failed to reload the TLS certificate: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tempfile::TempDir;
+
+    /// A CA with a server and a client certificate signed by it, written to a temporary directory.
+    pub struct TestCertificates {
+        pub dir: TempDir,
+    }
+
+    impl TestCertificates {
+        pub fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            let write = |name: &str, contents: String| {
+                std::fs::write(dir.path().join(name), contents).unwrap();
+            };
+
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(vec![]).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            write("ca.pem", ca.pem());
+
+            for name in ["server", "client"] {
+                let key = KeyPair::generate().unwrap();
+                let params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+                let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+                write(&format!("{}.pem", name), cert.pem());
+                write(&format!("{}-key.pem", name), key.serialize_pem());
+            }
+            TestCertificates { dir }
+        }
+
+        pub fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).to_string_lossy().to_string()
+        }
+
+        pub fn config(&self, client_auth: bool) -> TlsConfig {
+            TlsConfig {
+                cert: Some(self.path("server.pem")),
+                key: Some(self.path("server-key.pem")),
+                ca: Some(self.path("ca.pem")),
+                client_auth,
+                client_cert: Some(self.path("client.pem")),
+                client_key: Some(self.path("client-key.pem")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_server_config() {
+        let certificates = TestCertificates::new();
+        assert!(server_config(&certificates.config(false)).is_ok());
+        assert!(server_config(&certificates.config(true)).is_ok());
+
+        let mut config = certificates.config(true);
+        config.ca = None;
+        assert!(server_config(&config).is_err());
+
+        let mut config = certificates.config(false);
+        config.key = Some(certificates.path("server.pem"));
+        assert!(server_config(&config).is_err());
+    }
+}
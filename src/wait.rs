@@ -0,0 +1,208 @@
+/// This module lets clients wait for the status to reach a given health state and deployment
+/// phase, e.g. for a rollout script that waits for an instance to come online before moving on to
+/// the next one. The request is held until the status changes, without polling.
+use crate::problem::Problem;
+use crate::status::{DeploymentPhase, HealthState, Status};
+
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::time::sleep;
+
+/// How long a request waits if it doesn't specify a timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest a request can wait. Clients that need to wait longer make another request.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WaitQuery {
+    /// The health state to wait for: healthy or unhealthy.
+    pub state: Option<String>,
+    /// The deployment phase to wait for.
+    pub phase: Option<String>,
+    /// How long to wait, e.g. "300s" or "5m".
+    pub timeout: Option<String>,
+}
+
+/// The health state and deployment phase a client waits for. A condition without either is met
+/// right away.
+#[derive(Debug, Default, PartialEq)]
+pub struct Condition {
+    pub state: Option<HealthState>,
+    pub phase: Option<DeploymentPhase>,
+}
+
+impl Condition {
+    pub fn is_met(&self, status: &Status) -> bool {
+        self.state.is_none_or(|state| state == status.state)
+            && self
+                .phase
+                .as_ref()
+                .is_none_or(|phase| *phase == status.phase)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.state.map(|state| state.to_string()),
+            self.phase.as_ref().map(|phase| phase.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{}", parts.join(" and "))
+    }
+}
+
+impl WaitQuery {
+    fn parse(&self) -> Result<(Condition, Duration), Problem> {
+        let invalid =
+            |field: &str, detail: String| Problem::field(StatusCode::BAD_REQUEST, field, detail);
+        let state = match self.state.as_deref() {
+            Some(state) => {
+                Some(HealthState::try_from(state).map_err(|e| invalid("state", e.to_string()))?)
+            }
+            None => None,
+        };
+        let phase = match self.phase.as_deref() {
+            Some(phase) => Some(
+                DeploymentPhase::try_from(phase).map_err(|e| invalid("phase", e.to_string()))?,
+            ),
+            None => None,
+        };
+        let timeout = match self.timeout.as_deref() {
+            Some(timeout) => parse_duration(timeout).map_err(|e| invalid("timeout", e))?,
+            None => DEFAULT_TIMEOUT,
+        };
+        if timeout > MAX_TIMEOUT {
+            return Err(invalid(
+                "timeout",
+                format!(
+                    "The timeout can not exceed {} seconds.",
+                    MAX_TIMEOUT.as_secs()
+                ),
+            ));
+        }
+        Ok((Condition { state, phase }, timeout))
+    }
+}
+
+/// Parses a duration in whole seconds, minutes or hours, e.g. "300", "300s", "5m" or "1h".
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => 0,
+    };
+    match number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(seconds) if multiplier > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!(
+            "Invalid duration: {}. Use e.g. 30s, 5m or 1h.",
+            duration
+        )),
+    }
+}
+
+/// Returns the status as soon as it meets the condition of the query, or a 408 Request Timeout
+/// problem if it doesn't within the timeout. The status is checked again whenever it changes. When
+/// the server shuts down, waiting requests get a 503 Service Unavailable problem, so they don't hold
+/// up the shutdown.
+pub async fn wait(
+    status: Arc<Mutex<Status>>,
+    query: WaitQuery,
+    mut shutdown: watch::Receiver<bool>,
+) -> Response {
+    let (condition, timeout) = match query.parse() {
+        Ok(parsed) => parsed,
+        Err(problem) => return problem.into_response(),
+    };
+    let deadline = sleep(timeout);
+    tokio::pin!(deadline);
+
+    // Subscribe before checking the status, so a change between the check and the wait is seen.
+    let mut changes = status.lock().await.subscribe();
+    loop {
+        {
+            let status = status.lock().await;
+            if condition.is_met(&status) {
+                let body = serde_json::to_string(&*status).unwrap_or_default();
+                return (
+                    StatusCode::OK,
+                    [
+                        (header::CONTENT_TYPE, "application/json".to_string()),
+                        (header::ETAG, status.etag()),
+                    ],
+                    body,
+                )
+                    .into_response();
+            }
+        }
+        tokio::select! {
+            // The status outlives the request, so its changes never stop.
+            _ = changes.changed() => {}
+            Ok(_) = shutdown.wait_for(|stop| *stop) => {
+                return Problem::new(StatusCode::SERVICE_UNAVAILABLE, "The server is shutting down.")
+                    .into_response();
+            }
+            _ = &mut deadline => {
+                return Problem::new(
+                    StatusCode::REQUEST_TIMEOUT,
+                    format!("The status did not become {} within {}s.", condition, timeout.as_secs()),
+                )
+                .into_response();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("300"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("300s"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("1m30s").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn test_condition() {
+        let status = Status::new();
+        assert!(Condition::default().is_met(&status));
+        let condition = Condition {
+            state: Some(status.state),
+            phase: Some(DeploymentPhase::Maintenance),
+        };
+        assert_eq!(
+            condition.to_string(),
+            format!("{} and maintenance", status.state)
+        );
+        assert_eq!(
+            condition.is_met(&status),
+            status.phase == DeploymentPhase::Maintenance
+        );
+    }
+}
@@ -0,0 +1,216 @@
+/// This module implements the Zabbix agent passive check protocol, so the Zabbix server can poll
+/// the health status directly instead of through a UserParameter script.
+/// See https://www.zabbix.com/documentation/current/en/manual/appendix/protocols/zabbix_agent
+use crate::status::Status;
+
+use log::{debug, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// The header that precedes every message, followed by the little-endian data length.
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+/// Item keys are short, refuse anything that looks like it isn't a key.
+const MAX_KEY_LENGTH: usize = 2048;
+const NOT_SUPPORTED: &str = "ZBX_NOTSUPPORTED";
+/// How long to wait before accepting connections again after a failure, e.g. when we ran out of
+/// file descriptors, so the loop doesn't spin.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Accepts passive check connections and answers the requested item.
+pub async fn serve(listener: TcpListener, status: Arc<Mutex<Status>>) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept Zabbix connection: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let status = status.clone();
+        tokio::spawn(async move {
+            let key =
+                match tokio::time::timeout(Duration::from_secs(5), read_key(&mut stream)).await {
+                    Ok(Ok(key)) => key,
+                    Ok(Err(e)) => {
+                        warn!("Invalid Zabbix request from {}: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        warn!("Timed out reading Zabbix request from {}", peer);
+                        return;
+                    }
+                };
+            let value = item_value(&key, &*status.lock().await);
+            debug!("Zabbix item {} requested by {}: {:?}", key, peer, value);
+            if let Err(e) = stream.write_all(&encode(&value)).await {
+                warn!("Failed to reply to Zabbix request from {}: {}", peer, e);
+            }
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+/// Reads the requested item key. Current Zabbix servers send it with the protocol header, older
+/// ones send it as a plain line of text.
+async fn read_key<S>(stream: &mut S) -> Result<String, String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 5];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let key = if &header == HEADER {
+        let mut length = [0u8; 8];
+        stream
+            .read_exact(&mut length)
+            .await
+            .map_err(|e| e.to_string())?;
+        // The first four bytes are the data length, the last four are reserved.
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        if length > MAX_KEY_LENGTH {
+            return Err(format!("Request of {} bytes is too large", length));
+        }
+        let mut key = vec![0u8; length];
+        stream
+            .read_exact(&mut key)
+            .await
+            .map_err(|e| e.to_string())?;
+        key
+    } else {
+        let mut key = header.to_vec();
+        BufReader::new(stream.take(MAX_KEY_LENGTH as u64))
+            .read_until(b'\n', &mut key)
+            .await
+            .map_err(|e| e.to_string())?;
+        key
+    };
+
+    String::from_utf8(key)
+        .map(|key| key.trim().to_string())
+        .map_err(|_| "Item key is not valid UTF-8".to_string())
+}
+
+/// Returns the value of the given item key, or an error if the item is not supported.
+pub fn item_value(key: &str, status: &Status) -> Result<String, String> {
+    match key {
+        "agent.ping" => Ok("1".to_string()),
+        "healthmonitor.state" => Ok(status.state.to_string()),
+        "healthmonitor.phase" => Ok(status.phase.to_string()),
+        "healthmonitor.messages" => Ok(status.messages.join("\n")),
+        _ => {
+            let check = key
+                .strip_prefix("healthmonitor.check[")
+                .and_then(|key| key.strip_suffix(']'))
+                .ok_or_else(|| "Unsupported item key.".to_string())?;
+            let check = check.trim_matches('"');
+            match status.checks.get(check) {
+                Some(result) if result.passed => Ok("1".to_string()),
+                Some(_) => Ok("0".to_string()),
+                None => Err(format!("No results for check {}.", check)),
+            }
+        }
+    }
+}
+
+/// Encodes a reply, including the header and data length.
+fn encode(value: &Result<String, String>) -> Vec<u8> {
+    let data = match value {
+        Ok(value) => value.as_bytes().to_vec(),
+        Err(error) => [NOT_SUPPORTED.as_bytes(), b"\0", error.as_bytes()].concat(),
+    };
+    let mut message = HEADER.to_vec();
+    message.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    message.extend_from_slice(&data);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{DeploymentPhase, HealthState};
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_item_value() {
+        let mut status = Status::new();
+        assert_eq!(item_value("agent.ping", &status), Ok("1".to_string()));
+        assert_eq!(
+            item_value("healthmonitor.state", &status),
+            Ok("healthy".to_string())
+        );
+        assert_eq!(
+            item_value("healthmonitor.phase", &status),
+            Ok("online".to_string())
+        );
+
+        status.set_state(HealthState::Unhealthy);
+        status.set_phase(DeploymentPhase::Deploying);
+        assert_eq!(
+            item_value("healthmonitor.state", &status),
+            Ok("unhealthy".to_string())
+        );
+        assert_eq!(
+            item_value("healthmonitor.phase", &status),
+            Ok("deploying".to_string())
+        );
+
+        assert!(item_value("healthmonitor.check[FileCheck]", &status).is_err());
+        status.set_check_result("FileCheck", &Ok(()));
+        assert_eq!(
+            item_value("healthmonitor.check[FileCheck]", &status),
+            Ok("1".to_string())
+        );
+        status.set_check_result("FileCheck", &Err("File is empty".to_string()));
+        assert_eq!(
+            item_value("healthmonitor.check[\"FileCheck\"]", &status),
+            Ok("0".to_string())
+        );
+
+        assert!(item_value("system.cpu.load", &status).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let message = encode(&Ok("1".to_string()));
+        assert_eq!(message, b"ZBXD\x01\x01\0\0\0\0\0\0\x001");
+
+        let message = encode(&Err("Unsupported item key.".to_string()));
+        assert!(message.ends_with(b"ZBX_NOTSUPPORTED\0Unsupported item key."));
+    }
+
+    async fn request(addr: std::net::SocketAddr, request: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+        reply
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let status = Arc::new(Mutex::new(Status::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, status.clone()));
+
+        // A request using the protocol header.
+        let key = b"healthmonitor.state";
+        let mut message = HEADER.to_vec();
+        message.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        message.extend_from_slice(key);
+        let reply = request(addr, &message).await;
+        assert_eq!(reply, encode(&Ok("healthy".to_string())));
+
+        // A legacy plain text request.
+        status.lock().await.set_state(HealthState::Unhealthy);
+        let reply = request(addr, b"healthmonitor.state\n").await;
+        assert_eq!(reply, encode(&Ok("unhealthy".to_string())));
+    }
+}
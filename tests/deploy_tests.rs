@@ -0,0 +1,76 @@
+mod helpers;
+
+use helpers::*;
+use serial_test::serial;
+use std::env;
+use std::process::Output;
+use tokio::process::Command;
+
+/// Runs `cargo run -- <args>` and returns its output.
+async fn execute_command(args: &[&str]) -> Output {
+    Command::new("cargo")
+        .args(["run", "--"])
+        .args(args)
+        .output()
+        .await
+        .expect("The command should run.")
+}
+
+#[tokio::test]
+#[serial]
+async fn test_deploy() {
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_URLCHECK_URLS", "");
+
+    // Without a running server the deployment command is not executed.
+    let output = execute_command(&["deploy", "--", "echo", "Deploying"]).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Deploying"));
+
+    let _server = TestServer::start().await;
+
+    // A successful deployment puts the application online. The output of the command is streamed.
+    let output = execute_command(&[
+        "deploy",
+        "--version",
+        "1.2.0",
+        "--",
+        "sh",
+        "-c",
+        "echo Deploying",
+    ])
+    .await;
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Deploying\n");
+    let output = execute_command(&["phase", "get"]).await;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "online\n");
+    let output = execute_command(&["state", "get"]).await;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "healthy\n");
+
+    // A failed deployment marks the application as unhealthy, with the error output as message.
+    let output = execute_command(&[
+        "deploy",
+        "--",
+        "sh",
+        "-c",
+        "echo 'Database is locked' >&2; exit 3",
+    ])
+    .await;
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Database is locked"));
+    let output = execute_command(&["state", "get"]).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "unhealthy: The deployment command `sh -c echo 'Database is locked' >&2; exit 3` failed with exit code 3.\nDatabase is locked\n"
+    );
+
+    // Both deployments are in the history, and the first one is running.
+    let output = execute_command(&["phase", "history"]).await;
+    let history = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "running 1.2.0");
+    assert!(lines[1].contains("unknown version failed in"));
+    assert!(lines[2].contains("1.2.0 succeeded in"));
+}
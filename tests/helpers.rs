@@ -39,6 +39,25 @@ impl TestServer {
     pub async fn stop(&mut self) {
         stop_server(&mut self.server).await;
     }
+
+    /// Sends a signal to the server process.
+    pub fn signal(&self, signal: Signal) {
+        let pid = Pid::from_raw(
+            self.server
+                .id()
+                .expect("The server process should be running and have a process ID.")
+                as i32,
+        );
+        kill(pid, signal).expect("The signal should be sent.");
+    }
+
+    /// Waits for the server process to exit.
+    pub async fn wait(&mut self) -> std::process::ExitStatus {
+        self.server
+            .wait()
+            .await
+            .expect("The server process should exit.")
+    }
 }
 
 #[allow(dead_code)] // Not dead code, used in tests.
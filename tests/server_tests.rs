@@ -1,7 +1,11 @@
 mod helpers;
 
 use helpers::*;
+use nix::sys::signal::Signal;
 use serial_test::serial;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use tokio::process::Command;
 use tokio::time::sleep;
 
 #[tokio::test]
@@ -21,6 +25,38 @@ async fn test_stop_server_by_sending_sigint() {
     check_log_output_regex(server.stderr.clone(), expected_lines).await;
 }
 
+#[tokio::test]
+#[serial]
+async fn test_stop_server_by_sending_sigterm() {
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_SHUTDOWN_GRACE_PERIOD", "3");
+    let mut server = TestServer::start().await;
+    env::remove_var("HEALTHMONITOR_SHUTDOWN_GRACE_PERIOD");
+
+    // SIGUSR1 writes the status to the log.
+    server.signal(Signal::SIGUSR1);
+    sleep(tokio::time::Duration::from_millis(500)).await;
+
+    // On SIGTERM the server keeps running in the draining phase during the grace period.
+    server.signal(Signal::SIGTERM);
+    sleep(tokio::time::Duration::from_millis(500)).await;
+    let output = Command::new("cargo")
+        .args(["run", "--", "phase", "get"])
+        .output()
+        .await
+        .expect("The phase command should run.");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "draining\n");
+
+    assert!(server.wait().await.success());
+    let expected_lines = vec![
+        r#".*INFO.*Status: \{"state":.*"phase":"online".*\}"#,
+        ".*INFO.*Received SIGTERM, shutting down.*",
+        ".*INFO.*Draining for 3s before shutting down.*",
+        ".*INFO.*Server stopped.*",
+    ];
+    check_log_output_regex(server.stderr.clone(), expected_lines).await;
+}
+
 #[tokio::test]
 #[serial]
 async fn test_server_status() {
@@ -72,3 +108,183 @@ async fn test_server_status() {
         "The status command should exit with an error code if the server is not running."
     );
 }
+
+#[tokio::test]
+#[serial]
+async fn test_unix_socket() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let socket = dir.path().join("healthmonitor.sock");
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_URLCHECK_URLS", "");
+    env::set_var("HEALTHMONITOR_SERVER_SOCKET", &socket);
+    env::set_var("HEALTHMONITOR_SERVER_SOCKET_MODE", "600");
+    env::set_var("HEALTHMONITOR_SERVER_PORT", "");
+    let mut server = TestServer::start().await;
+
+    // The CLI talks to the server over the socket, which only the owner can use.
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "state",
+            "set",
+            "unhealthy",
+            "--message",
+            "Locked",
+        ])
+        .output()
+        .await
+        .expect("The state command should run.");
+    assert!(output.status.success());
+    let output = Command::new("cargo")
+        .args(["run", "--", "state", "get"])
+        .output()
+        .await
+        .expect("The state command should run.");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "unhealthy: Locked\n"
+    );
+
+    // There is no TCP listener.
+    assert!(std::net::TcpStream::connect("127.0.0.1:8080").is_err());
+
+    env::remove_var("HEALTHMONITOR_SERVER_SOCKET");
+    env::remove_var("HEALTHMONITOR_SERVER_SOCKET_MODE");
+    env::remove_var("HEALTHMONITOR_SERVER_PORT");
+    server.stop().await;
+    assert!(!socket.exists());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_admin_port() {
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_URLCHECK_URLS", "");
+    env::set_var("HEALTHMONITOR_ADMIN_PORT", "8081");
+    let mut server = TestServer::start().await;
+
+    // The CLI changes the status through the admin port.
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "state",
+            "set",
+            "unhealthy",
+            "--message",
+            "Locked",
+        ])
+        .output()
+        .await
+        .expect("The state command should run.");
+    assert!(output.status.success());
+
+    // The server port can only be used to read the status.
+    let client = reqwest::Client::new();
+    let response = client
+        .patch("http://127.0.0.1:8080/status")
+        .header("Content-Type", "application/json")
+        .body(r#"{"health": "healthy"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+    let response = client
+        .get("http://127.0.0.1:8080/status")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+    env::remove_var("HEALTHMONITOR_ADMIN_PORT");
+    server.stop().await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_token_authentication() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "def\n").unwrap();
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_URLCHECK_URLS", "");
+    env::set_var(
+        "HEALTHMONITOR_AUTH_TOKENS",
+        "haproxy:read:abc,cli:write:def",
+    );
+    let mut server = TestServer::start().await;
+
+    // Without a token the CLI is turned away.
+    let output = Command::new("cargo")
+        .args(["run", "--", "state", "set", "unhealthy"])
+        .output()
+        .await
+        .expect("The state command should run.");
+    assert!(!output.status.success());
+
+    // The CLI sends the token from the token file.
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "state",
+            "set",
+            "unhealthy",
+            "--message",
+            "Locked",
+        ])
+        .env("HEALTHMONITOR_TOKEN_FILE", &token_file)
+        .output()
+        .await
+        .expect("The state command should run.");
+    assert!(output.status.success());
+
+    // A read token can get the status, but not change it.
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://127.0.0.1:8080/status")
+        .bearer_auth("abc")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let response = client
+        .patch("http://127.0.0.1:8080/status")
+        .bearer_auth("abc")
+        .header("Content-Type", "application/json")
+        .body(r#"{"health": "healthy"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    env::remove_var("HEALTHMONITOR_AUTH_TOKENS");
+    server.stop().await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_stop_server_while_waiting() {
+    env::set_var("HEALTHMONITOR_FILECHECK_FILES", "");
+    env::set_var("HEALTHMONITOR_URLCHECK_URLS", "");
+    let mut server = TestServer::start().await;
+
+    // A request that waits for a phase that is never reached.
+    let waiting = tokio::spawn(
+        reqwest::Client::new()
+            .get("http://127.0.0.1:8080/status/wait?phase=maintenance&timeout=1h")
+            .send(),
+    );
+    sleep(tokio::time::Duration::from_millis(500)).await;
+    assert!(!waiting.is_finished());
+
+    // The server doesn't wait for the request to time out before stopping.
+    let started = std::time::Instant::now();
+    server.stop().await;
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    let response = waiting.await.unwrap().unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+}